@@ -0,0 +1,159 @@
+// Background components: polynomial baseline and broad underlying signal
+use serde::{Serialize, Deserialize};
+use crate::ent::{Param};
+use crate::lsh;
+use crate::lsh::{Mode};
+
+// Shape of the broad background
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum BroadShape {
+    Lorentzian,
+    Gaussian,
+}
+
+// Broad signal under the radicals, detected like them
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Broad {
+    pub shape: BroadShape,
+    pub amount: Param,
+    pub center: Param,  // From the center of the sweep (G)
+    pub lw: Param,  // Peak-to-peak width (G)
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Baseline {
+    pub poly: Vec<Param>,  // Polynomial coefficients on the field axis scaled to -1..1, constant first
+    pub broad: Option<Broad>,
+}
+
+impl Baseline {
+    pub fn with_broad(mut self, shape: BroadShape, amount: f64, center: f64, lw: f64) -> Baseline {
+        self.broad = Some(Broad {
+            shape,
+            amount: Param::set(amount, 0.0),
+            center: Param::set(center, 0.0),
+            lw: Param::set(lw, 0.0),
+        });
+        self
+    }
+
+    // Randomized copy within the variations; a broad signal keeps a positive width
+    pub fn randomize(&self) -> Baseline {
+        let mut baseline = self.clone();
+        for c in baseline.poly.iter_mut() { *c = c.randomize(); }
+        if let Some(broad) = &mut baseline.broad {
+            broad.amount = broad.amount.randomize();
+            broad.center = broad.center.randomize();
+            broad.lw = broad.lw.randomize();
+            if broad.lw.val < 0.0 { broad.lw.val = 0.0 }
+        }
+        baseline
+    }
+
+    // Polynomial on the field points
+    pub fn polynomial(&self, points: usize) -> Vec<f64> {
+        (0..points).map(|j| {
+            let x = axis(j, points);
+            self.poly.iter().rev().fold(0.0, |y, c| y*x+c.val)
+        }).collect()
+    }
+
+    // Broad signal on the field points, in the same mode as the radicals
+    pub fn broad(&self, sweep: f64, points: usize, mode: Mode, phase: f64) -> Vec<f64> {
+        let broad = match &self.broad {
+            Some(broad) if broad.lw.val > 0.0 => broad,
+            _ => return vec![0.0; points],
+        };
+        let incr = sweep/(points.max(2)-1) as f64;
+
+        (0..points).map(|j| {
+            let a = j as f64*incr-sweep/2.0-broad.center.val;
+            let line = match broad.shape {
+                BroadShape::Lorentzian => lsh::lorentzian(a, broad.lw.val, mode.harmonic()),
+                BroadShape::Gaussian => lsh::gaussian(a, broad.lw.val, mode.harmonic()),
+            };
+            broad.amount.val*mode.mix(line, phase)
+        }).collect()
+    }
+
+    // Least squares polynomial through the residual (experimental - simulated)
+    pub fn fit_polynomial(&mut self, residual: &[f64]) {
+        let (n, points) = (self.poly.len(), residual.len());
+        if n == 0 || points < n { return }
+
+        let mut mat = vec![vec![0.0; n]; n];
+        let mut rhs = vec![0.0; n];
+        for (j, r) in residual.iter().enumerate() {
+            let x = axis(j, points);
+            let pows: Vec<f64> = (0..n).map(|k| x.powi(k as i32)).collect();
+            for ((row, rhs), pk) in mat.iter_mut().zip(rhs.iter_mut()).zip(&pows) {
+                *rhs += pk*r;
+                for (m, pl) in row.iter_mut().zip(&pows) { *m += pk*pl; }
+            }
+        }
+
+        if let Some(coeffs) = solve(mat, rhs) {
+            for (c, val) in self.poly.iter_mut().zip(coeffs) { c.val += val; }
+        }
+    }
+
+    // Coefficients and broad signal, one per line
+    pub fn report(&self) -> String {
+        let mut report: String = self.poly.iter().enumerate()
+            .map(|(k, c)| format!("poly[{}]\t{:.6e}\n", k, c.val))
+            .collect();
+        if let Some(broad) = &self.broad {
+            report += &format!(
+                "broad {:?}\tamount {:.6e}\tcenter {:.4}\tlw {:.4}\n", broad.shape, broad.amount.val, broad.center.val, broad.lw.val
+            );
+        }
+        report
+    }
+}
+
+// Field point j scaled to -1..1
+fn axis(j: usize, points: usize) -> f64 {
+    if points < 2 { return 0.0 }
+    2.0*j as f64/(points-1) as f64-1.0
+}
+
+// Gaussian elimination with partial pivoting
+fn solve(mut mat: Vec<Vec<f64>>, mut rhs: Vec<f64>) -> Option<Vec<f64>> {
+    let n = rhs.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&a, &b| mat[a][col].abs().partial_cmp(&mat[b][col].abs()).unwrap())?;
+        if mat[pivot][col].abs() < 1E-300 { return None }
+        mat.swap(col, pivot);
+        rhs.swap(col, pivot);
+
+        let (upper, lower) = mat.split_at_mut(col+1);
+        let pivot_row = &upper[col];
+        for (row, r) in lower.iter_mut().zip(col+1..n) {
+            let f = row[col]/pivot_row[col];
+            for (m, p) in row[col..].iter_mut().zip(&pivot_row[col..]) { *m -= f*p; }
+            rhs[r] -= f*rhs[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row+1..n).map(|k| mat[row][k]*x[k]).sum();
+        x[row] = (rhs[row]-sum)/mat[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polynomial_fit_recovers_a_parabola() {
+        let mut baseline = Baseline { poly: vec![Param::set(0.0, 0.0); 3], broad: None };
+        let residual: Vec<f64> = (0..101).map(|j| { let x = axis(j, 101); 0.5-0.25*x+2.0*x*x }).collect();
+        baseline.fit_polynomial(&residual);
+        let coeffs: Vec<f64> = baseline.poly.iter().map(|c| c.val).collect();
+        assert!(coeffs.iter().zip(&[0.5, -0.25, 2.0]).all(|(c, e)| (c-e).abs() < 1E-9));
+        assert!(baseline.report().starts_with("poly[0]\t5.000000e-1"));
+    }
+}
@@ -0,0 +1,211 @@
+use num_complex::Complex64;
+
+// Dense complex matrix, row-major
+pub type Mat = Vec<Vec<Complex64>>;
+
+pub fn zeros(n: usize) -> Mat {
+    vec![vec![Complex64::new(0.0, 0.0); n]; n]
+}
+
+pub fn identity(n: usize) -> Mat {
+    let mut m = zeros(n);
+    for (i, row) in m.iter_mut().enumerate() { row[i] = Complex64::new(1.0, 0.0); }
+    m
+}
+
+pub fn add(a: &Mat, b: &Mat, scale: Complex64) -> Mat {
+    a.iter().zip(b.iter())
+        .map(|(ra, rb)| ra.iter().zip(rb.iter()).map(|(x, y)| x+scale*y).collect())
+        .collect()
+}
+
+pub fn mul(a: &Mat, b: &Mat) -> Mat {
+    let n = a.len();
+    let mut m = zeros(n);
+    for i in 0..n {
+        for k in 0..n {
+            if a[i][k] == Complex64::new(0.0, 0.0) { continue }
+            for j in 0..n { m[i][j] += a[i][k]*b[k][j]; }
+        }
+    }
+    m
+}
+
+// Eigenvalues (ascending) and eigenvectors (columns) of a Hermitian matrix.
+// The matrix is first split into the blocks that are not coupled by any element,
+// then every block is tridiagonalized and diagonalized separately.
+pub fn eigh(h: &Mat) -> (Vec<f64>, Mat) {
+    let n = h.len();
+    let mut vals = vec![0.0; n];
+    let mut vecs = zeros(n);
+
+    for block in blocks(h) {
+        let sub: Mat = block.iter().map(|&i| block.iter().map(|&j| h[i][j]).collect()).collect();
+        let (sub_vals, sub_vecs) = hermitian(sub);
+        for (k, &col) in block.iter().enumerate() {
+            vals[col] = sub_vals[k];
+            for (l, &row) in block.iter().enumerate() { vecs[row][col] = sub_vecs[l][k]; }
+        }
+    }
+
+    // Sort by energy
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| vals[a].partial_cmp(&vals[b]).unwrap());
+    let sorted_vals = order.iter().map(|&k| vals[k]).collect();
+    let sorted_vecs = (0..n).map(|i| order.iter().map(|&k| vecs[i][k]).collect()).collect();
+
+    (sorted_vals, sorted_vecs)
+}
+
+// Connected components of the non-zero pattern
+fn blocks(h: &Mat) -> Vec<Vec<usize>> {
+    let n = h.len();
+    components(n, (0..n).flat_map(|i| (i+1..n).map(move |j| (i, j))).filter(|&(i, j)| h[i][j].norm() > 0.0))
+}
+
+// Connected components of a graph of n vertices, each in ascending order
+pub fn components<I: IntoIterator<Item = (usize, usize)>>(n: usize, edges: I) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..n).collect();
+
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i { parent[i] = parent[parent[i]]; i = parent[i]; }
+        i
+    }
+
+    for (i, j) in edges {
+        let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+        if ri != rj { parent[ri] = rj; }
+    }
+
+    let mut blocks: Vec<Vec<usize>> = Vec::new();
+    let mut block_of = vec![usize::MAX; n];
+    for i in 0..n {
+        let r = root(&mut parent, i);
+        if block_of[r] == usize::MAX {
+            block_of[r] = blocks.len();
+            blocks.push(Vec::new());
+        }
+        blocks[block_of[r]].push(i);
+    }
+    blocks
+}
+
+// Householder reduction to a real tridiagonal matrix, then implicit QL
+fn hermitian(mut a: Mat) -> (Vec<f64>, Mat) {
+    let n = a.len();
+    let zero = Complex64::new(0.0, 0.0);
+    let mut q = identity(n);
+
+    // A <- H A H, H = I - 2 v v^H, zeroing the k-th column below the subdiagonal
+    for k in 0..n.saturating_sub(2) {
+        let norm = (k+1..n).map(|i| a[i][k].norm_sqr()).sum::<f64>().sqrt();
+        let below = (k+2..n).map(|i| a[i][k].norm_sqr()).sum::<f64>();
+        if norm == 0.0 || below == 0.0 { continue }
+
+        let phase = if a[k+1][k].norm() > 0.0 { a[k+1][k]/a[k+1][k].norm() } else { Complex64::new(1.0, 0.0) };
+        let alpha = -phase*norm;
+        let mut v = vec![zero; n];
+        for i in k+1..n { v[i] = a[i][k]; }
+        v[k+1] -= alpha;
+        let v_norm = v.iter().map(|x| x.norm_sqr()).sum::<f64>().sqrt();
+        for x in v.iter_mut() { *x /= v_norm; }
+
+        // A' = A - 2 (v w^H + w v^H), with p = A v, w = p - (v^H p) v;
+        // the rows and columns before k are already tridiagonal, so p and w vanish there
+        let p: Vec<Complex64> = (0..n).map(|i| if i < k { zero } else { (k+1..n).map(|j| a[i][j]*v[j]).sum() }).collect();
+        let kk: Complex64 = (k+1..n).map(|i| v[i].conj()*p[i]).sum();
+        let w: Vec<Complex64> = (0..n).map(|i| p[i]-kk*v[i]).collect();
+        for i in k..n {
+            for j in k..n {
+                a[i][j] -= 2.0*(v[i]*w[j].conj()+w[i]*v[j].conj());
+            }
+        }
+
+        // Q <- Q H
+        for row in q.iter_mut() {
+            let qv: Complex64 = (k+1..n).map(|j| row[j]*v[j]).sum();
+            for j in k+1..n { row[j] -= 2.0*qv*v[j].conj(); }
+        }
+    }
+
+    // Make the subdiagonal real with a diagonal phase transformation
+    let mut d: Vec<f64> = (0..n).map(|i| a[i][i].re).collect();
+    let mut e = vec![0.0; n];
+    let mut phase = Complex64::new(1.0, 0.0);
+    for i in 0..n.saturating_sub(1) {
+        let sub = a[i+1][i];
+        e[i] = sub.norm();
+        if e[i] > 0.0 { phase *= sub/e[i]; }
+        for row in q.iter_mut() { row[i+1] *= phase; }
+    }
+
+    // The tridiagonal eigenvectors are real: rotate them first, then back-transform once
+    let mut z: Vec<Vec<f64>> = (0..n).map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect()).collect();
+    tqli(&mut d, &mut e, &mut z);
+
+    let mut vecs = zeros(n);
+    for (row, q_row) in vecs.iter_mut().zip(q.iter()) {
+        for (&qk, z_row) in q_row.iter().zip(z.iter()) {
+            if qk == zero { continue }
+            for (x, &zk) in row.iter_mut().zip(z_row.iter()) { *x += qk*zk; }
+        }
+    }
+    (d, vecs)
+}
+
+// Eigenvalues of a real symmetric tridiagonal matrix (diagonal d, subdiagonal e),
+// rotations accumulated into the columns of z
+fn tqli(d: &mut [f64], e: &mut [f64], z: &mut [Vec<f64>]) {
+    let n = d.len();
+
+    for l in 0..n {
+        let mut iter = 0;
+        loop {
+            let mut m = l;
+            while m < n-1 {
+                let dd = d[m].abs()+d[m+1].abs();
+                if e[m].abs() <= f64::EPSILON*dd { break }
+                m += 1;
+            }
+            if m == l || iter == 60 { break }
+            iter += 1;
+
+            let mut g = (d[l+1]-d[l])/(2.0*e[l]);
+            let mut r = g.hypot(1.0);
+            g = d[m]-d[l]+e[l]/(g+r.copysign(g));
+            let (mut s, mut c, mut p) = (1.0, 1.0, 0.0);
+            let mut underflow = false;
+
+            for i in (l..m).rev() {
+                let f = s*e[i];
+                let b = c*e[i];
+                r = f.hypot(g);
+                e[i+1] = r;
+                if r == 0.0 {
+                    d[i+1] -= p;
+                    e[m] = 0.0;
+                    underflow = true;
+                    break
+                }
+                s = f/r;
+                c = g/r;
+                g = d[i+1]-p;
+                r = (d[i]-g)*s+2.0*c*b;
+                p = s*r;
+                d[i+1] = g+p;
+                g = c*r-b;
+
+                for row in z.iter_mut() {
+                    let f = row[i+1];
+                    row[i+1] = s*row[i]+c*f;
+                    row[i] = c*row[i]-s*f;
+                }
+            }
+            if underflow { continue }
+
+            d[l] -= p;
+            e[l] = g;
+            e[m] = 0.0;
+        }
+    }
+}
@@ -1,8 +1,13 @@
 use rand::prelude::*;
 use serde::{Serialize, Deserialize};
+use crate::ham::{Engine};
+use crate::iso;
+use crate::lsh;
+use crate::lsh::{Shape};
+use crate::sat;
 
 // Param
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct Param {
     pub val: f64,  // Value; starts with 0.0
     pub var: f64,  // Variation; starts with: 0.0
@@ -13,6 +18,11 @@ impl Param {
         Param { val, var, }
     }
 
+    // Electron spin 1/2
+    pub fn half() -> Param {
+        Param::set(0.5, 0.0)
+    }
+
     pub fn randomize(&self) -> Param {
         if self.var != 0.0 {
             let mut rng = thread_rng();
@@ -32,6 +42,10 @@ pub struct Nucleus {
     pub spin: Param,  // Nuclear spin;
     pub hpf: Param,  // Hyperfine constant;
     pub eqs: Param,  // Equivalent nucleus; Should be u8!
+    #[serde(default)]
+    pub iso: Option<String>,  // Isotope hpf refers to, e.g. "14N"
+    #[serde(default)]
+    pub abund: Vec<(String, f64)>,  // Isotopic mixture (fractions); empty for a pure isotope
 }
 
 impl Nucleus {
@@ -40,8 +54,114 @@ impl Nucleus {
             spin: Param::set(spin, 0.0),
             hpf: Param::set(hpf, 0.0),
             eqs: Param::set(eqs, 0.0),
+            iso: None,
+            abund: Vec::new(),
+        }
+    }
+
+    // Pure isotope; spin from the isotope table
+    pub fn isotope(symbol: &str, hpf: f64, eqs: f64) -> Nucleus {
+        let isotope = iso::get(symbol).expect("unknown isotope");
+        let mut nuc = Nucleus::set(isotope.spin, hpf, eqs);
+        nuc.iso = Some(String::from(symbol));
+        nuc
+    }
+
+    // Isotope in its natural abundance mixture (e.g. 13C satellites); hpf refers to symbol
+    pub fn natural(symbol: &str, hpf: f64, eqs: f64) -> Nucleus {
+        let mut nuc = Nucleus::isotope(symbol, hpf, eqs);
+        nuc.abund = iso::natural_abund(iso::get(symbol).unwrap().element);
+        nuc
+    }
+
+    // Element in natural abundance; hpf refers to its most abundant magnetic isotope
+    pub fn element(element: &str, hpf: f64, eqs: f64) -> Nucleus {
+        let isotope = iso::main_magnetic(element).expect("unknown element");
+        Nucleus::natural(isotope.symbol, hpf, eqs)
+    }
+
+    // Same nucleus as another isotope, with the coupling scaled by the gyromagnetic ratios
+    pub fn convert(&self, symbol: &str) -> Option<Nucleus> {
+        let from = self.iso.as_ref()?;
+        let isotope = iso::get(symbol)?;
+        let ratio = iso::convert_hpf(1.0, from, symbol)?;
+
+        let mut nuc = self.clone();
+        nuc.spin.val = isotope.spin;
+        nuc.hpf = Param::set(ratio*self.hpf.val, ratio.abs()*self.hpf.var);
+        nuc.iso = Some(String::from(symbol));
+        nuc.abund = Vec::new();
+        Some(nuc)
+    }
+
+    // Isotopologue groups of pure nuclei with their multinomial weights
+    pub fn isotopologues(&self) -> Vec<(f64, Vec<Nucleus>)> {
+        if self.abund.is_empty() || self.iso.is_none() { return vec![(1.0, vec![self.clone()])] }
+
+        let mut groups = Vec::new();
+        let eqs = self.eqs.val.round().max(0.0) as usize;
+        let mut counts = vec![0; self.abund.len()];
+        compositions(eqs, 0, &mut counts, &mut |counts: &[usize]| {
+            let mut weight = factorial(eqs);
+            let mut nucs = Vec::new();
+
+            for (k, (symbol, frac)) in counts.iter().zip(self.abund.iter()) {
+                weight *= frac.powi(*k as i32)/factorial(*k);
+                if *k == 0 { continue }
+                if let Some(mut nuc) = self.convert(symbol) {
+                    if nuc.spin.val == 0.0 { continue }
+                    nuc.eqs.val = *k as f64;
+                    nucs.push(nuc);
+                }
+            }
+
+            groups.push((weight, nucs));
+        });
+
+        groups
+    }
+}
+
+// All the ways to split n equivalent nuclei among counts.len() isotopes
+fn compositions(n: usize, idx: usize, counts: &mut Vec<usize>, f: &mut dyn FnMut(&[usize])) {
+    if idx == counts.len()-1 {
+        counts[idx] = n;
+        f(counts);
+        return
+    }
+
+    for k in 0..=n {
+        counts[idx] = k;
+        compositions(n-k, idx+1, counts, f);
+    }
+}
+
+fn factorial(n: usize) -> f64 {
+    (1..=n).fold(1.0, |acc, k| acc*k as f64)
+}
+
+// Isotopologues with a smaller fraction are neglected
+const ISOTOPOLOGUE_MIN: f64 = 1E-5;
+
+// Isotopologue groups of a set of nuclei with their weights
+fn isotopologue_groups(nucs: &[Nucleus]) -> Vec<(f64, Vec<Nucleus>)> {
+    let mut subs: Vec<(f64, Vec<Nucleus>)> = vec![(1.0, Vec::new())];
+
+    for nuc in nucs {
+        let mut new_subs = Vec::new();
+        for (weight, nucs) in &subs {
+            for (nuc_weight, nuc_group) in nuc.isotopologues() {
+                let weight = weight*nuc_weight;
+                if weight < ISOTOPOLOGUE_MIN { continue }
+                let mut nucs = nucs.clone();
+                nucs.extend(nuc_group);
+                new_subs.push((weight, nucs));
+            }
         }
+        subs = new_subs;
     }
+
+    subs
 }
 
 // Radical
@@ -51,9 +171,39 @@ pub struct Radical {
     // pub lwb: Param,
     // pub lwc: Param,
     pub lrtz: Param,  // Lorentzian linewidth parameter (%)
+    #[serde(default)]
+    pub shape: Shape,  // Pseudo-Voigt (lwa, lrtz) or Voigt (lwa Gaussian, lwl Lorentzian)
+    #[serde(default)]
+    pub lwl: Param,  // Lorentzian line width (Voigt only)
     pub amount: Param,  // Relative amount
     pub dh1: Param,
     pub nucs: Vec<Nucleus>,
+    #[serde(default)]
+    pub engine: Engine,  // First-order stick spectrum or exact Hamiltonian
+    #[serde(default = "Param::half")]
+    pub spin: Param,  // Electron spin S
+    #[serde(default)]
+    pub zfs_d: Param,  // Zero-field splitting D (G)
+    #[serde(default)]
+    pub zfs_e: Param,  // Zero-field splitting E (G)
+    #[serde(default)]
+    pub partner: Option<Box<Radical>>,  // Second electron of a biradical
+    #[serde(default)]
+    pub exch_j: Param,  // Exchange coupling J S1.S2 with the partner (G)
+    #[serde(default)]
+    pub dip_r: Param,  // Interspin distance for the dipolar coupling (nm); 0.0 neglects it
+    #[serde(default)]
+    pub exch_w: Param,  // Heisenberg spin exchange frequency between radicals (G)
+    #[serde(default)]
+    pub t1: Param,  // Spin-lattice relaxation time (us); 0.0 never saturates
+    #[serde(default)]
+    pub t2: Param,  // Spin-spin relaxation time (us)
+    #[serde(default)]
+    pub pol_net: Param,  // Net polarization, relative to the thermal one (negative is emissive)
+    #[serde(default)]
+    pub pol_mult: Param,  // Multiplet polarization, relative to the thermal one (positive is E/A)
+    #[serde(default)]
+    pub pol_pair: Option<usize>,  // Radical pair partner (index in the radicals) for the multiplet pattern
 }
 
 impl Radical {
@@ -61,9 +211,24 @@ impl Radical {
         Self {
             lwa: Param::set(lwa, 0.0),
             lrtz: Param::set(lrtz, 0.0),
+            shape: Shape::PseudoVoigt,
+            lwl: Param::set(0.0, 0.0),
             amount: Param::set(amount, 0.0),
             dh1: Param::set(dh1, 0.0),
             nucs,
+            engine: Engine::Perturbation,
+            spin: Param::half(),
+            zfs_d: Param::set(0.0, 0.0),
+            zfs_e: Param::set(0.0, 0.0),
+            partner: None,
+            exch_j: Param::set(0.0, 0.0),
+            dip_r: Param::set(0.0, 0.0),
+            exch_w: Param::set(0.0, 0.0),
+            t1: Param::set(0.0, 0.0),
+            t2: Param::set(0.0, 0.0),
+            pol_net: Param::set(0.0, 0.0),
+            pol_mult: Param::set(0.0, 0.0),
+            pol_pair: None,
         }
     }
 
@@ -80,6 +245,34 @@ impl Radical {
            ("lwa", "var") => self_clone.lwa.var = new_val,
            ("lrtz", "val") => self_clone.lrtz.val = new_val,
            ("lrtz", "var") => self_clone.lrtz.var = new_val,
+           ("lwl", "val") => self_clone.lwl.val = new_val,
+           ("lwl", "var") => self_clone.lwl.var = new_val,
+           ("shape", "val") => self_clone.shape = if new_val == 1.0 { Shape::Voigt } else { Shape::PseudoVoigt },
+           ("engine", "val") => self_clone.engine = match new_val as usize {
+               1 => Engine::Exact,
+               2 => Engine::Powder,
+               _ => Engine::Perturbation,
+           },
+           ("spin", "val") => { self_clone.spin.val = new_val; self_clone.physical_spin() },
+           ("zfs_d", "val") => { self_clone.zfs_d.val = new_val; self_clone.physical_spin() },
+           ("zfs_d", "var") => self_clone.zfs_d.var = new_val,
+           ("zfs_e", "val") => { self_clone.zfs_e.val = new_val; self_clone.physical_spin() },
+           ("zfs_e", "var") => self_clone.zfs_e.var = new_val,
+           ("exch_j", "val") => self_clone.exch_j.val = new_val,
+           ("exch_j", "var") => self_clone.exch_j.var = new_val,
+           ("dip_r", "val") => self_clone.dip_r.val = new_val,
+           ("dip_r", "var") => self_clone.dip_r.var = new_val,
+           ("exch_w", "val") => self_clone.exch_w.val = new_val,
+           ("exch_w", "var") => self_clone.exch_w.var = new_val,
+           ("t1", "val") => self_clone.t1.val = new_val,
+           ("t1", "var") => self_clone.t1.var = new_val,
+           ("t2", "val") => self_clone.t2.val = new_val,
+           ("t2", "var") => self_clone.t2.var = new_val,
+           ("pol_net", "val") => self_clone.pol_net.val = new_val,
+           ("pol_net", "var") => self_clone.pol_net.var = new_val,
+           ("pol_mult", "val") => self_clone.pol_mult.val = new_val,
+           ("pol_mult", "var") => self_clone.pol_mult.var = new_val,
+           ("pol_pair", "val") => self_clone.pol_pair = if new_val >= 1.0 { Some(new_val as usize-1) } else { None },
            _ => panic!("unknown field"),
        };
 
@@ -107,6 +300,84 @@ impl Radical {
         if rad.lrtz.val < 0.0 { rad.lrtz.val = 0.0 };
         if rad.amount.val < 0.0 { rad.amount.val = 0.0 };
         if rad.lrtz.val > 100.0 { rad.lrtz.val = 100.0 };
+        if rad.lwl.val < 0.0 { rad.lwl.val = 0.0 };
+        rad.physical_spin();
+        if rad.dip_r.val < 0.0 { rad.dip_r.val = 0.0 };
+        if rad.exch_w.val < 0.0 { rad.exch_w.val = 0.0 };
+        if rad.t1.val < 0.0 { rad.t1.val = 0.0 };
+        if rad.t2.val < 0.0 { rad.t2.val = 0.0 };
+        rad
+    }
+
+    // Half-integer spin, at least 1/2, and |E| <= |D|/3: what ham::spin_ops can build
+    fn physical_spin(&mut self) {
+        self.spin.val = ((2.0*self.spin.val).round()/2.0).max(0.5);
+        if self.zfs_e.val.abs() > self.zfs_d.val.abs()/3.0 { self.zfs_e.val = self.zfs_e.val.signum()*self.zfs_d.val.abs()/3.0 };
+    }
+
+    // Randomized copy within the variations: every parameter with var > 0, the partner's too
+    pub fn randomize(&self) -> Radical {
+        let mut rad = self.clone();
+        for par in [
+            &mut rad.lwa, &mut rad.lrtz, &mut rad.lwl, &mut rad.amount, &mut rad.dh1,
+            &mut rad.zfs_d, &mut rad.zfs_e, &mut rad.exch_j, &mut rad.dip_r, &mut rad.exch_w,
+            &mut rad.t1, &mut rad.t2, &mut rad.pol_net, &mut rad.pol_mult,
+        ] {
+            *par = par.randomize();
+        }
+        for nuc in rad.nucs.iter_mut() { nuc.hpf = nuc.hpf.randomize(); }
+        rad.partner = rad.partner.map(|partner| Box::new(partner.randomize()));
+        Radical::check_pars(rad)
+    }
+
+    // Line (absorption, dispersion) at distance a from the center, deriv-th derivative
+    pub fn line(&self, a: f64, deriv: usize) -> (f64, f64) {
+        match self.shape {
+            Shape::PseudoVoigt => lsh::lineshape(a, self.lwa.val, self.lrtz.val, deriv),
+            Shape::Voigt => lsh::voigt(a, self.lwa.val, self.lwl.val, deriv),
+        }
+    }
+
+    // Isotopologue sub-radicals, with amounts weighted by their abundance
+    pub fn isotopologues(&self) -> Vec<Radical> {
+        let partner_subs = match &self.partner {
+            Some(partner) => isotopologue_groups(&partner.nucs),
+            None => vec![(1.0, Vec::new())],
+        };
+
+        let mut rads = Vec::new();
+        for (weight, nucs) in isotopologue_groups(&self.nucs) {
+            for (partner_weight, partner_nucs) in &partner_subs {
+                let weight = weight*partner_weight;
+                if weight < ISOTOPOLOGUE_MIN { continue }
+
+                let mut rad = self.clone();
+                rad.amount.val *= weight;
+                rad.nucs = nucs.clone();
+                if let Some(partner) = rad.partner.as_mut() { partner.nucs = partner_nucs.clone(); }
+                rads.push(rad);
+            }
+        }
+        rads
+    }
+
+    // Radical under a microwave field b1 (G): the Lorentzian width grows as 1/sqrt(s) and the area as sqrt(s).
+    // The pseudo-Voigt lwa is broadened for its Lorentzian share.
+    pub fn saturated(&self, b1: f64) -> Radical {
+        let s = sat::factor(b1, self.t1.val, self.t2.val);
+        let mut rad = self.clone();
+        match rad.shape {
+            Shape::Voigt => rad.lwl.val /= s.sqrt(),
+            Shape::PseudoVoigt => rad.lwa.val *= 1.0+rad.lrtz.val/100.0*(1.0/s.sqrt()-1.0),
+        }
+        rad.amount.val *= s.sqrt();
+        rad
+    }
+
+    // Biradical: the partner's dh1 sets its g shift, J (G) the exchange coupling
+    pub fn biradical(mut rad: Radical, partner: Radical, exch_j: f64) -> Radical {
+        rad.partner = Some(Box::new(partner));
+        rad.exch_j.val = exch_j;
         rad
     }
 
@@ -118,7 +389,79 @@ impl Radical {
     // Debug function!
     pub fn probe() -> Radical {
         let mut rad = Radical::set(0.5, 100.0, 100.0, 0.0, Vec::new());
-        rad.nucs.push(Nucleus::set(1.0, 14.0, 1.0));
+        rad.nucs.push(Nucleus::isotope("14N", 14.0, 1.0));
         rad
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edited_spin_is_physical() {
+        let rad = Radical::electron();
+        let set = |rad: &Radical, fld: &str, val: f64| rad.set_radpar(fld.to_string(), "val".to_string(), val);
+        assert_eq!(set(&rad, "spin", 1.3).spin.val, 1.5);
+        assert_eq!(set(&rad, "spin", 1.2).spin.val, 1.0);
+        assert_eq!(set(&rad, "spin", 0.0).spin.val, 0.5);
+
+        // E within a third of D, whichever is edited
+        let rad = set(&set(&rad, "spin", 1.0), "zfs_d", 30.0);
+        assert_eq!(set(&rad, "zfs_e", -25.0).zfs_e.val, -10.0);
+        let rad = set(&rad, "zfs_e", 8.0);
+        assert_eq!(rad.zfs_e.val, 8.0);
+        assert_eq!(set(&rad, "zfs_d", -12.0).zfs_e.val, 4.0);
+    }
+
+    #[test]
+    fn isotopologue_weights() {
+        // Two carbons: 12C2, 12C13C, 13C2; only 13C carries a spin
+        let groups = Nucleus::element("C", 10.0, 2.0).isotopologues();
+        let total: f64 = groups.iter().map(|(weight, _)| weight).sum();
+        assert!((total-1.0).abs() < 1E-12);
+        let (c12, c13) = (0.9893, 0.0107);
+        let weights: Vec<(f64, f64)> = groups.iter()
+            .map(|(weight, nucs)| (*weight, nucs.iter().map(|nuc| nuc.eqs.val).sum::<f64>()))
+            .collect();
+        assert!(weights.iter().any(|&(w, n)| n == 0.0 && (w-c12*c12).abs() < 1E-12));
+        assert!(weights.iter().any(|&(w, n)| n == 1.0 && (w-2.0*c12*c13).abs() < 1E-12));
+        assert!(weights.iter().any(|&(w, n)| n == 2.0 && (w-c13*c13).abs() < 1E-12));
+    }
+
+    #[test]
+    fn isotopologue_amounts() {
+        let mut rad = Radical::set(1.0, 0.0, 3.0, 0.0, vec![Nucleus::isotope("14N", 15.0, 1.0), Nucleus::element("C", 5.0, 4.0)]);
+        rad.nucs[0].abund = vec![(String::from("14N"), 0.5), (String::from("15N"), 0.5)];
+        let amount: f64 = rad.isotopologues().iter().map(|iso| iso.amount.val).sum();
+        assert!((amount-3.0).abs() < 1E-3, "{}", amount);
+
+        // 15N couples by the ratio of the gyromagnetic ratios
+        let n15 = rad.nucs[0].convert("15N").unwrap();
+        assert_eq!(n15.spin.val, 0.5);
+        assert!((n15.hpf.val/15.0+1.4028).abs() < 1E-3, "{}", n15.hpf.val);
+    }
+
+    #[test]
+    fn spin_is_half_integer() {
+        let mut rad = Radical::electron();
+        for (spin, rounded) in [(0.0, 0.5), (0.7, 0.5), (0.8, 1.0), (2.4, 2.5), (-1.0, 0.5)].iter() {
+            rad.spin.val = *spin;
+            assert_eq!(Radical::check_pars(rad.clone()).spin.val, *rounded);
+        }
+    }
+
+    #[test]
+    fn randomize_within_variations() {
+        let mut rad = Radical::probe();
+        rad.exch_w = Param::set(2.0, 1.0);
+        rad.pol_net = Param::set(0.0, 3.0);
+        rad.nucs[0].hpf.var = 0.5;
+        for _ in 0..100 {
+            let trial = rad.randomize();
+            assert!((trial.exch_w.val-2.0).abs() <= 1.0 && (trial.pol_net.val).abs() <= 3.0);
+            assert!((trial.nucs[0].hpf.val-14.0).abs() <= 0.5);
+            assert_eq!((trial.lwa.val, trial.amount.val, trial.t1.val), (rad.lwa.val, rad.amount.val, rad.t1.val));
+        }
+    }
+}
@@ -0,0 +1,386 @@
+// EasySpin scripts: radicals as Sys structures and the settings as Exp, for garlic
+use crate::ent::{Nucleus, Param, Radical};
+use crate::ham;
+use crate::ham::{BMAGN, GE};
+use crate::iso;
+use crate::lsh::{Mode, Shape};
+use crate::sim::{Simulator};
+use std::collections::HashMap;
+use std::fmt;
+
+// Gaussian and Lorentzian FWHM to peak-to-peak widths of the first derivative
+const FWHM_PP_GAUSS: f64 = 0.8493218;  // 1/sqrt(2 ln 2)
+const FWHM_PP_LRTZ: f64 = 0.5773503;  // 1/sqrt(3)
+
+// Comment with the pseudo-Voigt widths of a spin system, which EasySpin can't express
+const PSEUDO_VOIGT: &str = "% g-factor:";
+
+// Value of a field in the script
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Str(String),
+    Num(Vec<f64>),
+}
+
+// Experiment read from a script; missing fields keep the simulator settings
+#[derive(Clone, Debug, Default)]
+pub struct Experiment {
+    pub freq: Option<f64>,  // GHz
+    pub range: Option<(f64, f64)>,  // G
+    pub points: Option<usize>,
+    pub harmonic: Option<usize>,
+    pub mod_amp: Option<f64>,  // G
+    pub phase: Option<f64>,  // Radians
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Value { line: usize, text: String },  // Not a string, number or array
+    Isotope(String),
+    NoSystem,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Value { line, text } => write!(f, "line {}: can't read \"{}\"", line, text),
+            ScriptError::Isotope(symbol) => write!(f, "unknown isotope or element \"{}\"", symbol),
+            ScriptError::NoSystem => write!(f, "no spin system (a structure with g or Nucs)"),
+        }
+    }
+}
+
+// Script simulating the radicals with the simulator settings
+pub fn to_easyspin(sim: &Simulator, rads: &[Radical]) -> String {
+    let freq = *sim.freq.lock().unwrap();
+    let axis = sim.field_axis();
+    let mode = *sim.mode.lock().unwrap();
+    let mod_amp = *sim.mod_amp.lock().unwrap();
+    let phase = sim.phase.lock().unwrap().val;
+    let b0 = ham::center_field(freq);
+
+    let mut out = String::from("% g-factor model for EasySpin garlic; fields in mT, couplings in MHz\nclear Sys* Exp\n\n");
+    let mut names = Vec::new();
+    for (r, rad) in rads.iter().enumerate() {
+        let name = format!("Sys{}", r+1);
+        let g = GE*b0/(b0+rad.dh1.val);
+
+        out += &format!("{}.g = {:.8};\n", name, g);
+        if rad.spin.val != 0.5 { out += &format!("{}.S = {};\n", name, rad.spin.val); }
+        if rad.zfs_d.val != 0.0 || rad.zfs_e.val != 0.0 {
+            out += &format!("{}.D = [{} {}];\n", name, mhz(rad.zfs_d.val, g), mhz(rad.zfs_e.val, g));
+        }
+
+        // Natural mixtures go by element, their coupling referred to the most abundant magnetic isotope
+        let nucs: Vec<(String, f64, usize)> = rad.nucs.iter().filter(|nuc| nuc.eqs.val.round() >= 1.0).map(|nuc| {
+            let eqs = nuc.eqs.val.round() as usize;
+            match (&nuc.iso, nuc.abund.is_empty()) {
+                (Some(symbol), true) => (symbol.clone(), nuc.hpf.val, eqs),
+                (Some(symbol), false) => {
+                    let element = iso::get(symbol).map_or("", |isotope| isotope.element);
+                    let main = iso::main_magnetic(element).map_or(symbol.clone(), |isotope| isotope.symbol.to_string());
+                    let hpf = iso::convert_hpf(nuc.hpf.val, symbol, &main).unwrap_or(nuc.hpf.val);
+                    (element.to_string(), hpf, eqs)
+                },
+                // Spin only: the first isotope with that spin
+                (None, _) => {
+                    let symbol = iso::ISOTOPES.iter().find(|isotope| isotope.spin == nuc.spin.val && isotope.gamma != 0.0)
+                        .map_or("1H", |isotope| isotope.symbol);
+                    (symbol.to_string(), nuc.hpf.val, eqs)
+                },
+            }
+        }).collect();
+        if !nucs.is_empty() {
+            let symbols: Vec<&str> = nucs.iter().map(|(symbol, _, _)| symbol.as_str()).collect();
+            let ns: Vec<String> = nucs.iter().map(|(_, _, n)| n.to_string()).collect();
+            let a: Vec<String> = nucs.iter().map(|(_, hpf, _)| mhz(*hpf, g)).collect();
+            out += &format!("{}.Nucs = '{}';\n{}.n = [{}];\n{}.A = [{}];\n", name, symbols.join(","), name, ns.join(" "), name, a.join(" "));
+        }
+
+        // Peak-to-peak widths; the pseudo-Voigt sum becomes a convolution, and the comment keeps the sum for the import
+        let (gauss, lrtz) = match rad.shape {
+            Shape::Voigt => (rad.lwa.val, rad.lwl.val),
+            Shape::PseudoVoigt => (rad.lwa.val*(1.0-rad.lrtz.val/100.0), rad.lwa.val*rad.lrtz.val/100.0),
+        };
+        out += &format!("{}.lwpp = [{} {}];\n", name, gauss/10.0, lrtz/10.0);
+        if rad.shape == Shape::PseudoVoigt && gauss > 0.0 && lrtz > 0.0 {
+            out += &format!("{} {} pseudo-Voigt lwa {} lrtz {}  (lwpp approximates it)\n", PSEUDO_VOIGT, name, rad.lwa.val, rad.lrtz.val);
+        }
+        out += &format!("{}.weight = {};\n", name, rad.amount.val);
+
+        let missing: Vec<&str> = vec![
+            ("biradical partner", rad.partner.is_some()),
+            ("spin exchange", rad.exch_w.val != 0.0),
+            ("saturation", rad.t1.val != 0.0),
+            ("polarization", rad.pol_net.val != 0.0 || rad.pol_mult.val != 0.0),
+        ].into_iter().filter(|(_, set)| *set).map(|(what, _)| what).collect();
+        if !missing.is_empty() { out += &format!("% Not exported: {}\n", missing.join(", ")); }
+        out += "\n";
+        names.push(name);
+    }
+
+    let (first, last) = (axis.first().cloned().unwrap_or(0.0), axis.last().cloned().unwrap_or(0.0));
+    out += &format!("Exp.mwFreq = {};  % GHz\n", freq);
+    out += &format!("Exp.Range = [{} {}];\n", first/10.0, last/10.0);
+    out += &format!("Exp.nPoints = {};\n", axis.len());
+    out += &format!("Exp.Harmonic = {};\n", mode.harmonic());
+    if mod_amp > 0.0 { out += &format!("Exp.ModAmp = {};\n", mod_amp/10.0); }
+    out += &format!("Exp.mwPhase = {};\n\n", (mode.phase()+phase).to_radians());
+
+    out += &format!("[B, spc] = garlic({{{}}}, Exp);\nplot(B, spc);\n", names.join(", "));
+    out
+}
+
+// Coupling in G as MHz for an electron with g
+fn mhz(hpf: f64, g: f64) -> String {
+    format!("{:.6}", hpf*g*BMAGN)
+}
+
+// Radicals and experiment of a script; freq is used when Exp.mwFreq is missing
+pub fn from_easyspin(script: &str, freq: f64) -> Result<(Vec<Radical>, Experiment), ScriptError> {
+    let structs = assignments(script)?;
+    let pseudo_voigt = pseudo_voigt(script);
+    let number = |fields: &HashMap<String, Value>, key: &str| match fields.get(key) {
+        Some(Value::Num(values)) => Some(values.clone()),
+        _ => None,
+    };
+
+    // Experiment: the structure with a microwave frequency
+    let mut exp = Experiment::default();
+    if let Some((_, fields)) = structs.iter().find(|(_, fields)| fields.contains_key("mwFreq")) {
+        exp.freq = number(fields, "mwFreq").and_then(|v| v.first().cloned());
+        exp.range = match (number(fields, "Range"), number(fields, "CenterSweep")) {
+            (Some(range), _) if range.len() == 2 => Some((range[0]*10.0, range[1]*10.0)),
+            (_, Some(cs)) if cs.len() == 2 => Some(((cs[0]-cs[1]/2.0)*10.0, (cs[0]+cs[1]/2.0)*10.0)),
+            _ => None,
+        };
+        exp.points = number(fields, "nPoints").and_then(|v| v.first().map(|n| *n as usize));
+        exp.harmonic = number(fields, "Harmonic").and_then(|v| v.first().map(|n| *n as usize));
+        exp.mod_amp = number(fields, "ModAmp").and_then(|v| v.first().map(|amp| amp*10.0));
+        exp.phase = number(fields, "mwPhase").and_then(|v| v.first().cloned());
+    }
+    let b0 = ham::center_field(exp.freq.unwrap_or(freq));
+
+    // Spin systems, in the order they appear
+    let mut rads = Vec::new();
+    for (name, fields) in structs.iter().filter(|(_, fields)| fields.contains_key("g") || fields.contains_key("Nucs")) {
+        // Isotropic g: the mean of the principal values
+        let g = number(fields, "g").map_or(GE, |g| g.iter().sum::<f64>()/g.len().max(1) as f64);
+        let mut rad = Radical::set(1.0, 0.0, 1.0, b0*(GE/g-1.0), Vec::new());
+
+        if let Some(Value::Str(symbols)) = fields.get("Nucs") {
+            let symbols: Vec<&str> = symbols.split(',').map(|symbol| symbol.trim()).filter(|symbol| !symbol.is_empty()).collect();
+            let ns = number(fields, "n").unwrap_or_default();
+            let a = number(fields, "A").unwrap_or_default();
+            // One isotropic value per nucleus, or three principal values each
+            let per_nuc = if a.len() == 3*symbols.len() && !symbols.is_empty() { 3 } else { 1 };
+            for (k, symbol) in symbols.iter().enumerate() {
+                let a_mhz = a.chunks(per_nuc).nth(k).map_or(0.0, |a| a.iter().sum::<f64>()/a.len() as f64);
+                let hpf = a_mhz/(g*BMAGN);
+                let eqs = ns.get(k).cloned().unwrap_or(1.0);
+                let nuc = if iso::get(symbol).is_some() {
+                    Nucleus::isotope(symbol, hpf, eqs)
+                } else if iso::main_magnetic(symbol).is_some() {
+                    Nucleus::element(symbol, hpf, eqs)
+                } else {
+                    return Err(ScriptError::Isotope(symbol.to_string()))
+                };
+                rad.nucs.push(nuc);
+            }
+        }
+
+        if let Some(s) = number(fields, "S").and_then(|s| s.first().cloned()) { rad.spin = Param::set(s, 0.0); }
+        if let Some(d) = number(fields, "D") {
+            rad.zfs_d = Param::set(d.first().cloned().unwrap_or(0.0)/(g*BMAGN), 0.0);
+            rad.zfs_e = Param::set(d.get(1).cloned().unwrap_or(0.0)/(g*BMAGN), 0.0);
+        }
+        if let Some(weight) = number(fields, "weight").and_then(|w| w.first().cloned()) { rad.amount = Param::set(weight, 0.0); }
+
+        // Widths: peak-to-peak, or FWHM; Gaussian first
+        let widths = number(fields, "lwpp").map(|lw| (lw.first().cloned().unwrap_or(0.0), lw.get(1).cloned().unwrap_or(0.0)))
+            .or_else(|| number(fields, "lw").map(|lw| (
+                lw.first().cloned().unwrap_or(0.0)*FWHM_PP_GAUSS, lw.get(1).cloned().unwrap_or(0.0)*FWHM_PP_LRTZ,
+            )));
+        if let Some((gauss, lrtz)) = widths {
+            let (gauss, lrtz) = (gauss*10.0, lrtz*10.0);
+            match (gauss > 0.0, lrtz > 0.0) {
+                (true, true) => { rad.shape = Shape::Voigt; rad.lwa.val = gauss; rad.lwl.val = lrtz; },
+                (false, true) => { rad.lwa.val = lrtz; rad.lrtz.val = 100.0; },
+                _ => { rad.lwa.val = gauss; },
+            }
+        }
+        if let Some(&(lwa, lrtz)) = pseudo_voigt.get(name) {
+            rad.shape = Shape::PseudoVoigt;
+            rad.lwa.val = lwa;
+            rad.lrtz.val = lrtz;
+        }
+
+        rads.push(Radical::check_pars(rad));
+    }
+
+    if rads.is_empty() { return Err(ScriptError::NoSystem) }
+    Ok((rads, exp))
+}
+
+impl Experiment {
+    pub fn apply(&self, sim: &Simulator) {
+        if let Some(freq) = self.freq { *sim.freq.lock().unwrap() = freq; }
+        if let Some((first, last)) = self.range {
+            *sim.center.lock().unwrap() = (first+last)/2.0;
+            *sim.sweep.lock().unwrap() = last-first;
+        }
+        if let Some(points) = self.points { sim.set_points(points); }
+        if let Some(mod_amp) = self.mod_amp { *sim.mod_amp.lock().unwrap() = mod_amp; }
+
+        let mode = match (self.harmonic, self.phase) {
+            (Some(0), Some(phase)) if (phase-std::f64::consts::FRAC_PI_2).abs() < 0.3 => Some(Mode::Dispersion),
+            (Some(0), _) => Some(Mode::Absorption),
+            (Some(1), _) => Some(Mode::FirstDerivative),
+            (Some(2), _) => Some(Mode::SecondDerivative),
+            _ => None,
+        };
+        if let Some(mode) = mode {
+            *sim.mode.lock().unwrap() = mode;
+            // The rest of the phase is the detection phase error
+            if let Some(phase) = self.phase { sim.phase.lock().unwrap().val = phase.to_degrees()-mode.phase(); }
+        }
+    }
+}
+
+// Pseudo-Voigt widths (lwa, lrtz) by spin system, from the comments written by to_easyspin
+fn pseudo_voigt(script: &str) -> HashMap<String, (f64, f64)> {
+    script.lines().filter_map(|line| {
+        let words: Vec<&str> = line.trim().strip_prefix(PSEUDO_VOIGT)?.split_whitespace().collect();
+        match words.as_slice() {
+            [name, "pseudo-Voigt", "lwa", lwa, "lrtz", lrtz, ..] => Some((name.to_string(), (lwa.parse().ok()?, lrtz.parse().ok()?))),
+            _ => None,
+        }
+    }).collect()
+}
+
+// Structures by name, in the order they appear
+type Structs = Vec<(String, HashMap<String, Value>)>;
+
+// Fields assigned to each structure, as Name.field = value; or Name = struct('field', value, ...);
+fn assignments(script: &str) -> Result<Structs, ScriptError> {
+    let mut structs: Structs = Vec::new();
+    let mut assign = |name: &str, field: &str, value: Value| {
+        match structs.iter_mut().find(|(n, _)| n == name) {
+            Some((_, fields)) => { fields.insert(field.to_string(), value); },
+            None => structs.push((name.to_string(), vec![(field.to_string(), value)].into_iter().collect())),
+        }
+    };
+
+    for (n, line) in script.lines().enumerate() {
+        for statement in split_top(strip_comment(line), ';') {
+            let statement = statement.trim();
+            let mut parts = statement.splitn(2, '=');
+            let (target, value) = match (parts.next(), parts.next()) {
+                (Some(target), Some(value)) if !value.starts_with('=') => (target.trim(), value.trim()),
+                _ => continue,
+            };
+            let error = || ScriptError::Value { line: n+1, text: value.to_string() };
+
+            if let Some(dot) = target.find('.') {
+                let (name, field) = (&target[..dot], &target[dot+1..]);
+                if !is_identifier(name) || !is_identifier(field) { continue }
+                assign(name, field, parse_value(value).ok_or_else(error)?);
+            } else if is_identifier(target) && value.starts_with("struct(") && value.ends_with(')') {
+                let args = split_top(&value[7..value.len()-1], ',');
+                for pair in args.chunks(2) {
+                    if let [field, value] = pair {
+                        match parse_value(field) {
+                            Some(Value::Str(field)) => assign(target, &field, parse_value(value).ok_or_else(error)?),
+                            _ => return Err(error()),
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(structs)
+}
+
+// A quoted string, a number or an array of numbers
+fn parse_value(text: &str) -> Option<Value> {
+    let text = text.trim();
+    if text.len() >= 2 && (text.starts_with('\'') && text.ends_with('\'') || text.starts_with('"') && text.ends_with('"')) {
+        return Some(Value::Str(text[1..text.len()-1].to_string()))
+    }
+    let inner = if text.starts_with('[') && text.ends_with(']') { &text[1..text.len()-1] } else { text };
+    let values: Option<Vec<f64>> = inner.split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .filter(|number| !number.is_empty())
+        .map(|number| match number {
+            "pi" => Some(std::f64::consts::PI),
+            "pi/2" => Some(std::f64::consts::FRAC_PI_2),
+            _ => number.parse().ok(),
+        })
+        .collect();
+    values.map(Value::Num)
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.chars().next().is_some_and(|c| c.is_ascii_alphabetic()) && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// % starts a comment outside strings
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (k, c) in line.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            '%' if !quoted => return &line[..k],
+            _ => (),
+        }
+    }
+    line
+}
+
+// Split outside quotes, brackets and parentheses
+fn split_top(text: &str, sep: char) -> Vec<&str> {
+    let (mut parts, mut start, mut depth, mut quoted) = (Vec::new(), 0, 0, false);
+    for (k, c) in text.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            '[' | '(' | '{' if !quoted => depth += 1,
+            ']' | ')' | '}' if !quoted => depth -= 1,
+            c if c == sep && !quoted && depth == 0 => { parts.push(&text[start..k]); start = k+1; },
+            _ => (),
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let sim = Simulator::new();
+        let mut pseudo = Radical::probe();
+        pseudo.lwa.val = 1.2;
+        pseudo.lrtz.val = 30.0;
+        pseudo.dh1.val = -2.5;
+        let mut voigt = Radical::electron();
+        voigt.shape = Shape::Voigt;
+        voigt.lwa.val = 0.8;
+        voigt.lwl.val = 0.3;
+        voigt.nucs.push(Nucleus::isotope("1H", 5.1, 2.0));
+
+        let script = to_easyspin(&sim, &[pseudo, voigt]);
+        assert!(script.contains("% g-factor: Sys1 pseudo-Voigt lwa 1.2 lrtz 30"));
+        let (rads, exp) = from_easyspin(&script, 1.0).unwrap();
+        assert_eq!(exp.freq, Some(*sim.freq.lock().unwrap()));
+
+        assert_eq!((rads[0].shape, rads[0].lwa.val, rads[0].lrtz.val), (Shape::PseudoVoigt, 1.2, 30.0));
+        assert!((rads[0].dh1.val+2.5).abs() < 1E-4);
+        assert!((rads[0].nucs[0].hpf.val-14.0).abs() < 1E-4);
+        assert_eq!(rads[0].nucs[0].iso.as_deref(), Some("14N"));
+
+        assert_eq!(rads[1].shape, Shape::Voigt);
+        assert!((rads[1].lwa.val-0.8).abs() < 1E-9 && (rads[1].lwl.val-0.3).abs() < 1E-9);
+        assert!((rads[1].nucs[0].hpf.val-5.1).abs() < 1E-4 && rads[1].nucs[0].eqs.val == 2.0);
+    }
+}
@@ -0,0 +1,164 @@
+// Heisenberg spin exchange between the lines of a radical (Anderson's model).
+// Every collision moves the spin to a line drawn from the stick spectrum:
+// the lines broaden by W(1-p), shift towards the center, mix with dispersion and collapse in the fast limit.
+use crate::stk::{Stick};
+use num_complex::Complex64;
+use std::f64::consts::PI;
+
+pub const GAMMA_E: f64 = 1.76085963E7;  // Electron gyromagnetic ratio (rad s^-1 G^-1)
+
+// Exchanged spectrum F = S/(1-WS), S = sum p/(hwhm + W + i(a-pos)), with its derivatives.
+// sticks must have unitary total intensity; hwhm is the intrinsic Lorentzian half width and w the exchange frequency (G).
+// Returns (absorption, dispersion) with unit area, like the lineshapes.
+pub fn line(a: f64, sticks: &[Stick], hwhm: f64, w: f64, deriv: usize) -> (f64, f64) {
+    let one = Complex64::new(1.0, 0.0);
+    let (mut s, mut s1, mut s2) = (Complex64::new(0.0, 0.0), Complex64::new(0.0, 0.0), Complex64::new(0.0, 0.0));
+
+    for stick in sticks {
+        let d = Complex64::new(hwhm+w, a-stick.pos);
+        s += stick.int/d;
+        s1 += Complex64::new(0.0, -stick.int)/(d*d);
+        s2 += -2.0*stick.int/(d*d*d);
+    }
+
+    let den = one-w*s;
+    let f = match deriv {
+        0 => s/den,
+        1 => s1/(den*den),
+        _ => s2/(den*den) + 2.0*w*s1*s1/(den*den*den),
+    };
+
+    (f.re/PI, -f.im/PI)
+}
+
+// Intrinsic Lorentzian half width and remaining Gaussian (peak-to-peak) width of a radical's line.
+// The pseudo-Voigt lwa is split between the two by lrtz.
+pub fn widths(lwa: f64, lrtz: f64, lwl: f64, voigt: bool) -> (f64, f64) {
+    let (lw_l, lw_g) = if voigt { (lwl, lwa) } else { (lwa*lrtz/100.0, lwa*(1.0-lrtz/100.0)) };
+    (3.0_f64.sqrt()/2.0*lw_l, lw_g)
+}
+
+// Gaussian inhomogeneous broadening of a spectrum on the field points (unit sum kernel)
+pub fn broaden(spec: &[f64], lw: f64, incr: f64) -> Vec<f64> {
+    let sigma = lw/2.0;
+    if sigma < incr/2.0 { return spec.to_vec() }
+
+    let half = (4.0*sigma/incr).ceil() as isize;
+    let kernel: Vec<f64> = (-half..=half).map(|k| (-0.5*(k as f64*incr/sigma).powi(2)).exp()).collect();
+    let total: f64 = kernel.iter().sum();
+
+    let n = spec.len() as isize;
+    (0..n).map(|j| {
+        kernel.iter().enumerate().map(|(k, g)| {
+            let i = j+k as isize-half;
+            if i < 0 || i >= n { 0.0 } else { spec[i as usize]*g }
+        }).sum::<f64>()/total
+    }).collect()
+}
+
+// Exchange rate (s^-1) of an exchange frequency in field units (G)
+pub fn rate(w: f64) -> f64 {
+    GAMMA_E*w
+}
+
+// Concentration (M) from the exchange frequency and the bimolecular rate constant (M^-1 s^-1)
+pub fn concentration(w: f64, k_ex: f64) -> f64 {
+    rate(w)/k_ex
+}
+
+// Oxygen partial pressure (mmHg) from the exchange frequency, the Heisenberg rate constant
+// with O2 (M^-1 s^-1) and the O2 solubility (M/mmHg)
+pub fn oxygen(w: f64, k_ex: f64, solubility: f64) -> f64 {
+    concentration(w, k_ex)/solubility
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsh;
+
+    // Triplet of a nitroxide, unit total intensity
+    fn triplet() -> Vec<Stick> {
+        vec![Stick { pos: -15.0, int: 0.25 }, Stick { pos: 0.0, int: 0.5 }, Stick { pos: 15.0, int: 0.25 }]
+    }
+
+    #[test]
+    fn no_exchange_is_the_lorentzian_sum() {
+        let (hwhm, lw) = (0.6, 1.2/3.0_f64.sqrt());
+        for deriv in 0..3 {
+            for &a in &[-16.0, -3.2, 0.0, 0.4, 14.1] {
+                let (abs, disp) = line(a, &triplet(), hwhm, 0.0, deriv);
+                let sum = triplet().iter().map(|s| {
+                    let (abs, disp) = lsh::lorentzian(a-s.pos, lw, deriv);
+                    (s.int*abs, s.int*disp)
+                }).fold((0.0, 0.0), |acc, x| (acc.0+x.0, acc.1+x.1));
+                assert!((abs-sum.0).abs() < 1E-12 && (disp-sum.1).abs() < 1E-12, "{} {}", deriv, a);
+            }
+        }
+    }
+
+    #[test]
+    fn area_is_preserved() {
+        // Far from the lines F goes back to 1/(hwhm+ia): integrate far out and add the Lorentzian tails
+        let range = 2000.0;
+        let step = 0.01;
+        for &w in &[0.0, 2.0, 10.0, 100.0] {
+            let area: f64 = (0..(2.0*range/step) as usize).map(|j| line(-range+(j as f64+0.5)*step, &triplet(), 0.5, w, 0).0*step).sum();
+            let tail = 2.0*0.5/(PI*range);
+            assert!((area+tail-1.0).abs() < 1E-3, "{} {}", w, area);
+        }
+    }
+
+    #[test]
+    fn fast_exchange_collapses_to_the_center() {
+        // Off-centre weights: the collapsed line sits at their mean
+        let sticks = vec![Stick { pos: -10.0, int: 0.2 }, Stick { pos: 5.0, int: 0.8 }];
+        let center = -10.0*0.2+5.0*0.8;
+        let abs = |a: f64| line(a, &sticks, 0.3, 1E4, 0).0;
+        let peak = (0..4001).map(|j| -20.0+j as f64*0.01).max_by(|a, b| abs(*a).total_cmp(&abs(*b))).unwrap();
+        assert!((peak-center).abs() < 0.011, "{}", peak);
+
+        // A single line: the first derivative changes sign only once
+        let deriv: Vec<f64> = (0..4001).map(|j| line(-20.0+j as f64*0.01, &sticks, 0.3, 1E4, 1).0).collect();
+        assert_eq!(deriv.windows(2).filter(|d| d[0].signum() != d[1].signum()).count(), 1);
+    }
+
+    #[test]
+    fn derivatives_match_finite_differences() {
+        let h = 1E-4;
+        for &w in &[0.5, 5.0] {
+            for &a in &[-14.0, -6.0, 0.3, 9.0] {
+                let f = |a: f64, deriv: usize| line(a, &triplet(), 0.8, w, deriv);
+                let (d1, d2) = (f(a, 1), f(a, 2));
+                let num1 = ((f(a+h, 0).0-f(a-h, 0).0)/(2.0*h), (f(a+h, 0).1-f(a-h, 0).1)/(2.0*h));
+                let num2 = ((f(a+h, 1).0-f(a-h, 1).0)/(2.0*h), (f(a+h, 1).1-f(a-h, 1).1)/(2.0*h));
+                assert!((d1.0-num1.0).abs() < 1E-6 && (d1.1-num1.1).abs() < 1E-6, "{} {}", w, a);
+                assert!((d2.0-num2.0).abs() < 1E-6 && (d2.1-num2.1).abs() < 1E-6, "{} {}", w, a);
+            }
+        }
+    }
+
+    #[test]
+    fn widths_and_broadening() {
+        // Pure Lorentzian pseudo-Voigt: all of it to the exchange lines
+        let (hwhm, lw_g) = widths(2.0, 100.0, 0.0, false);
+        assert!((hwhm-3.0_f64.sqrt()).abs() < 1E-12 && lw_g == 0.0);
+        assert_eq!(widths(1.5, 0.0, 0.7, true), (3.0_f64.sqrt()/2.0*0.7, 1.5));
+
+        // The unit sum kernel keeps the area away from the ends; narrower than a point does nothing
+        let spec: Vec<f64> = (0..201).map(|j| if j == 100 { 1.0 } else { 0.0 }).collect();
+        let wide = broaden(&spec, 4.0, 0.1);
+        assert!((wide.iter().sum::<f64>()-1.0).abs() < 1E-12);
+        assert_eq!(wide.iter().cloned().fold(0.0, f64::max), wide[100]);
+        assert_eq!(broaden(&spec, 0.05, 0.1), spec);
+    }
+
+    #[test]
+    fn rate_and_concentration_round_trip() {
+        let (w, k_ex, solubility) = (0.35, 3.5E9, 1.7E-6);
+        assert!((rate(w)/GAMMA_E-w).abs() < 1E-15);
+        let c = concentration(w, k_ex);
+        assert!((c*k_ex/GAMMA_E-w).abs() < 1E-15);
+        assert!((oxygen(w, k_ex, solubility)*solubility-c).abs() < 1E-18);
+    }
+}
@@ -0,0 +1,59 @@
+// Radix-2 fast Fourier transform, for the convolutions
+use num_complex::Complex64;
+use std::f64::consts::PI;
+
+// Smallest power of two not below n
+pub fn length(n: usize) -> usize {
+    n.max(1).next_power_of_two()
+}
+
+// In place transform; the length must be a power of two. The inverse is scaled by 1/n.
+pub fn fft(buf: &mut [Complex64], inverse: bool) {
+    let n = buf.len();
+    if n < 2 { return }
+
+    // Bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 { j ^= bit; bit >>= 1; }
+        j |= bit;
+        if i < j { buf.swap(i, j); }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let w_len = Complex64::from_polar(1.0, sign*2.0*PI/len as f64);
+        for start in (0..n).step_by(len) {
+            let mut w = Complex64::new(1.0, 0.0);
+            for k in 0..len/2 {
+                let (u, v) = (buf[start+k], buf[start+k+len/2]*w);
+                buf[start+k] = u+v;
+                buf[start+k+len/2] = u-v;
+                w *= w_len;
+            }
+        }
+        len <<= 1;
+    }
+
+    if inverse {
+        for x in buf.iter_mut() { *x /= n as f64; }
+    }
+}
+
+// Transform of a real sequence zero-padded to len
+pub fn forward(x: &[f64], len: usize) -> Vec<Complex64> {
+    let mut buf = vec![Complex64::new(0.0, 0.0); len];
+    for (b, x) in buf.iter_mut().zip(x) { *b = Complex64::new(*x, 0.0); }
+    fft(&mut buf, false);
+    buf
+}
+
+// Linear convolution of a transformed sequence with a real one; len must hold both
+pub fn convolve(a_ft: &[Complex64], b: &[f64]) -> Vec<f64> {
+    let mut buf = forward(b, a_ft.len());
+    for (b, a) in buf.iter_mut().zip(a_ft) { *b *= a; }
+    fft(&mut buf, true);
+    buf.iter().map(|x| x.re).collect()
+}
@@ -0,0 +1,56 @@
+// Acquisition filter of the lock-in: first order RC low-pass along the sweep
+use serde::{Serialize, Deserialize};
+
+// Field sweep direction
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum Direction {
+    #[default]
+    Up,  // From low to high field
+    Down,
+}
+
+// RC filter with time constant tc and conversion time per point conv (same unit, e.g. ms).
+// The filter runs in acquisition order, so lines are delayed and tailed along the sweep;
+// tc = 0.0 leaves the spectrum untouched.
+pub fn rc(spec: &[f64], tc: f64, conv: f64, dir: Direction) -> Vec<f64> {
+    if tc <= 0.0 || conv <= 0.0 { return spec.to_vec() }
+    let k = 1.0-(-conv/tc).exp();
+
+    let mut out = spec.to_vec();
+    let order: Vec<usize> = match dir {
+        Direction::Up => (0..spec.len()).collect(),
+        Direction::Down => (0..spec.len()).rev().collect(),
+    };
+
+    let mut y = match order.first() { Some(&i) => spec[i], None => return out };
+    for i in order {
+        y += k*(spec[i]-y);
+        out[i] = y;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_response() {
+        // A step rises as 1 - exp(-t/tc), t counted from the step
+        let step: Vec<f64> = (0..100).map(|i| if i < 10 { 0.0 } else { 1.0 }).collect();
+        let out = rc(&step, 10.24, 1.28, Direction::Up);
+        for (n, y) in out[10..].iter().enumerate() {
+            assert!((y-(1.0-(-((n+1) as f64)*1.28/10.24).exp())).abs() < 1E-12);
+        }
+    }
+
+    #[test]
+    fn down_sweep_mirrors_up() {
+        let line: Vec<f64> = (0..64).map(|i| (-((i as f64-20.0)/4.0).powi(2)).exp()).collect();
+        let mirrored: Vec<f64> = line.iter().rev().cloned().collect();
+        let up = rc(&line, 2.0, 1.0, Direction::Up);
+        let down = rc(&mirrored, 2.0, 1.0, Direction::Down);
+        assert!(up.iter().zip(down.iter().rev()).all(|(a, b)| (a-b).abs() < 1E-12));
+        assert_eq!(rc(&line, 0.0, 1.0, Direction::Up), line);
+    }
+}
@@ -0,0 +1,612 @@
+use num_complex::Complex64;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::fmt;
+
+use crate::eig;
+use crate::eig::{Mat};
+use crate::ent::{Radical};
+use crate::stk;
+use crate::stk::{Stick};
+
+// Simulation engine of a radical
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum Engine {
+    #[default]
+    Perturbation,  // First-order stick spectrum
+    Exact,  // Diagonalization of the spin Hamiltonian; zero-field splitting averaged out (solution)
+    Powder,  // Diagonalization of the spin Hamiltonian, averaged over the orientations
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HamError {
+    TooLarge { dim: usize, block: usize },  // Hilbert space and its largest block
+}
+
+impl fmt::Display for HamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HamError::TooLarge { dim, block } => write!(
+                f, "spin system too large for the exact engine: dimension {}, largest block {} (at most {})",
+                dim, block, MAX_BLOCK
+            ),
+        }
+    }
+}
+
+pub const GE: f64 = 2.00231930436;  // Free electron g
+pub const BMAGN: f64 = 1.399624493;  // Bohr magneton / h (MHz/G)
+pub const DIPOLAR: f64 = 18.5695;  // Dipolar coupling of two free electrons 1 nm apart (G)
+
+const MAX_DIM: usize = 1 << 16;  // Largest Hilbert space
+const MAX_BLOCK: usize = 512;  // Largest block to diagonalize
+const SEARCH_STEPS: usize = 64;  // Field points of the resonance search
+const SEARCH_TOL: f64 = 1E-7;  // Resonance field tolerance (G)
+const PROB_MIN: f64 = 1E-6;  // Weaker transitions are neglected
+const MIXING: f64 = 1E-3;  // Relative change of the resonance slope across a grid step, beyond which it's refined
+const POWDER_THETA: usize = 31;  // Orientations between the z axis and the xy plane
+const POWDER_PHI: usize = 8;  // Orientations in the xy plane (rhombic systems only)
+
+// Resonance field (G) of a free electron at the microwave frequency (GHz)
+pub fn center_field(freq: f64) -> f64 {
+    1000.0*freq/(GE*BMAGN)
+}
+
+// Microwave frequency (GHz) of a free electron resonating at field (G)
+pub fn resonance_freq(field: f64) -> f64 {
+    field*GE*BMAGN/1000.0
+}
+
+// Sz, S+, S- in the |m> basis, m = s, s-1, ..., -s
+pub fn spin_ops(s: f64) -> (Mat, Mat, Mat) {
+    let mult = (2.0*s).round() as usize + 1;
+    let (mut sz, mut sp, mut sm) = (eig::zeros(mult), eig::zeros(mult), eig::zeros(mult));
+
+    for i in 0..mult {
+        let m = s-i as f64;
+        sz[i][i] = Complex64::new(m, 0.0);
+        if i > 0 {
+            let c = (s*(s+1.0)-m*(m+1.0)).sqrt();
+            sp[i-1][i] = Complex64::new(c, 0.0);  // S+|m> = c|m+1>
+            sm[i][i-1] = Complex64::new(c, 0.0);
+        }
+    }
+
+    (sz, sp, sm)
+}
+
+// Sx, Sy, Sz of a single spin
+pub fn cartesian(s: f64) -> [Mat; 3] {
+    let (sz, sp, sm) = spin_ops(s);
+    let sx = scale(&eig::add(&sp, &sm, Complex64::new(1.0, 0.0)), Complex64::new(0.5, 0.0));
+    let sy = scale(&eig::add(&sp, &sm, Complex64::new(-1.0, 0.0)), Complex64::new(0.0, -0.5));
+    [sx, sy, sz]
+}
+
+// Sparse operator on the product space: (row, col, value), repeated elements add up
+type Sparse = Vec<(usize, usize, Complex64)>;
+
+// c times the product of single-spin operators (distinct spins k), identity on the other spins.
+// The first spin is the most significant digit of the product state index.
+fn product(c: Complex64, factors: &[(usize, &Mat)], mults: &[usize]) -> Sparse {
+    let mut strides = vec![1; mults.len()];
+    for k in (0..mults.len().saturating_sub(1)).rev() { strides[k] = strides[k+1]*mults[k+1]; }
+    let dim = strides.first().map_or(1, |s| s*mults[0]);
+
+    let mut elems = Vec::new();
+    for col in 0..dim {
+        let mut rows = vec![(col, c)];
+        for &(k, op) in factors {
+            let m = (col/strides[k])%mults[k];
+            let stride = strides[k];
+            rows = rows.iter().flat_map(|&(row, x)| {
+                (0..mults[k]).filter(|&r| op[r][m] != Complex64::new(0.0, 0.0))
+                    .map(move |r| (row+r*stride-m*stride, x*op[r][m]))
+            }).collect();
+        }
+        elems.extend(rows.into_iter().map(|(row, x)| (row, col, x)));
+    }
+    elems
+}
+
+// c A.B = c (Az Bz + (A+ B- + A- B+)/2) for the spins a and b, from their Sz, S+, S-:
+// the ladder operators keep the elements that cancel out (A+ B+, A- B-) away from the pattern
+fn dot(c: f64, a: (usize, &(Mat, Mat, Mat)), b: (usize, &(Mat, Mat, Mat)), mults: &[usize]) -> Sparse {
+    let (c, half) = (Complex64::new(c, 0.0), Complex64::new(0.5*c, 0.0));
+    let mut elems = product(c, &[(a.0, &(a.1).0), (b.0, &(b.1).0)], mults);
+    elems.extend(product(half, &[(a.0, &(a.1).1), (b.0, &(b.1).2)], mults));
+    elems.extend(product(half, &[(a.0, &(a.1).2), (b.0, &(b.1).1)], mults));
+    elems
+}
+
+// Isotropic hyperfine coupling: (electron, nucleus, a)
+type Hyperfine = (usize, usize, f64);
+
+// Electron spins first (the radical and, in a biradical, its partner), then the nuclei,
+// equivalent ones expanded one by one
+fn expand(rad: &Radical) -> (Vec<&Radical>, Vec<f64>, Vec<Hyperfine>) {
+    let mut elecs: Vec<&Radical> = vec![rad];
+    if let Some(partner) = &rad.partner { elecs.push(partner); }
+
+    let mut spins: Vec<f64> = elecs.iter().map(|elec| elec.spin.val).collect();
+    let mut hpfs = Vec::new();
+    for (e, elec) in elecs.iter().enumerate() {
+        for nuc in &elec.nucs {
+            if nuc.spin.val <= 0.0 { continue }
+            for _ in 0..nuc.eqs.val.round().max(0.0) as usize {
+                hpfs.push((e, spins.len(), nuc.hpf.val));
+                spins.push(nuc.spin.val);
+            }
+        }
+    }
+    (elecs, spins, hpfs)
+}
+
+// Whether the exact engine can treat the radical. Without orientation every term conserves
+// the total Mz, so the largest block is the most populated Mz value; a powder is diagonalized whole.
+pub fn check(rad: &Radical, engine: Engine) -> Result<(), HamError> {
+    let (_, spins, _) = expand(rad);
+    let mults: Vec<usize> = spins.iter().map(|s| (2.0*s).round() as usize + 1).collect();
+    let dim = mults.iter().try_fold(1usize, |d, &m| d.checked_mul(m)).unwrap_or(usize::MAX);
+    if dim > MAX_DIM { return Err(HamError::TooLarge { dim, block: dim }) }
+
+    let block = match engine {
+        Engine::Powder => dim,
+        _ => {
+            // Number of product states per total 2Mz, by convolution
+            let mut counts = vec![1usize];
+            for &mult in &mults {
+                let mut next = vec![0; counts.len()+mult-1];
+                for (i, &n) in counts.iter().enumerate() {
+                    for k in 0..mult { next[i+k] += n; }
+                }
+                counts = next;
+            }
+            counts.into_iter().max().unwrap_or(1)
+        },
+    };
+
+    if block > MAX_BLOCK { Err(HamError::TooLarge { dim, block }) } else { Ok(()) }
+}
+
+// Product states coupled at some field, with the Hamiltonian restricted to them
+struct Block {
+    h0: Mat,  // Field-independent part
+    gz: Mat,  // Electron Zeeman per unit field
+}
+
+impl Block {
+    // Sorted energies, eigenvectors and their Hellmann-Feynman slopes
+    fn levels(&self, field: f64) -> Levels {
+        let (vals, vecs) = eig::eigh(&eig::add(&self.h0, &self.gz, Complex64::new(field, 0.0)));
+        let n = vals.len();
+        let zero = Complex64::new(0.0, 0.0);
+
+        // <i|Gz|i> from the rows of Gz V
+        let mut slopes = vec![0.0; n];
+        for (gz_row, v_row) in self.gz.iter().zip(vecs.iter()) {
+            let mut gzv = vec![zero; n];
+            for (b, &x) in gz_row.iter().enumerate() {
+                if x == zero { continue }
+                for (y, &vb) in gzv.iter_mut().zip(vecs[b].iter()) { *y += x*vb; }
+            }
+            for (slope, (v, y)) in slopes.iter_mut().zip(v_row.iter().zip(gzv.iter())) { *slope += (v.conj()*y).re; }
+        }
+        Levels { vals, vecs, slopes }
+    }
+}
+
+struct Levels {
+    vals: Vec<f64>,
+    vecs: Mat,
+    slopes: Vec<f64>,
+}
+
+// Microwave field coupling of the blocks p <= q, one (rows of p, columns of q) matrix per component
+struct Link {
+    p: usize,
+    q: usize,
+    ops: Vec<Mat>,
+}
+
+// Spin Hamiltonian in field units (G at the free electron g): H(B) = H0 + B Gz,
+// split into the blocks that neither H0 nor Gz couple
+pub struct SpinSystem {
+    blocks: Vec<Block>,
+    links: Vec<Link>,  // Block pairs coupled by the microwave field
+    n_b1: usize,  // Electron spin components perpendicular to the field
+}
+
+impl SpinSystem {
+    // Isotropic system (solution): field along z, zero-field splitting and dipolar coupling averaged out
+    pub fn new(rad: &Radical, hv: f64) -> SpinSystem {
+        SpinSystem::build(rad, None, hv)
+    }
+
+    // Field along (theta, phi) in the zero-field splitting frame (interspin vector along z)
+    pub fn oriented(rad: &Radical, theta: f64, phi: f64, hv: f64) -> SpinSystem {
+        SpinSystem::build(rad, Some((theta, phi)), hv)
+    }
+
+    // Electron spins with zero-field splitting, isotropic hyperfine couplings,
+    // exchange J S1.S2 and dipolar coupling. The partner's dh1 shifts its g value.
+    // The operators are built sparse on the product states; see check for the size.
+    fn build(rad: &Radical, dir: Option<(f64, f64)>, hv: f64) -> SpinSystem {
+        let (elecs, spins, hpfs) = expand(rad);
+        let mults: Vec<usize> = spins.iter().map(|s| (2.0*s).round() as usize + 1).collect();
+        let dim: usize = mults.iter().product();
+
+        let c = |x: f64| Complex64::new(x, 0.0);
+        let ops: Vec<[Mat; 3]> = spins.iter().map(|&s| cartesian(s)).collect();
+        let ladders: Vec<(Mat, Mat, Mat)> = spins.iter().map(|&s| spin_ops(s)).collect();
+
+        // Isotropic hyperfine a S.I
+        let mut h0: Sparse = Vec::new();
+        for (e, k, a) in hpfs {
+            h0.extend(dot(a, (e, &ladders[e]), (k, &ladders[k]), &mults));
+        }
+
+        // Exchange J S1.S2
+        if elecs.len() == 2 {
+            h0.extend(dot(rad.exch_j.val, (0, &ladders[0]), (1, &ladders[1]), &mults));
+        }
+
+        // Electron Zeeman, relative to the first electron
+        let zeeman: Vec<f64> = elecs.iter().map(|elec| hv/(hv+elec.dh1.val-rad.dh1.val)).collect();
+        let project = |x: f64, y: f64, z: f64, weights: &[f64]| -> Sparse {
+            (0..elecs.len()).flat_map(|e| {
+                [x, y, z].iter().enumerate().filter(|(_, &w)| w != 0.0)
+                    .flat_map(|(q, &w)| product(c(w*weights[e]), &[(e, &ops[e][q])], &mults))
+                    .collect::<Sparse>()
+            }).collect()
+        };
+        let ones = vec![1.0; elecs.len()];
+
+        let (theta, phi) = match dir {
+            None => return SpinSystem::assemble(dim, h0, project(0.0, 0.0, 1.0, &zeeman), vec![project(1.0, 0.0, 0.0, &ones)]),
+            Some(dir) => dir,
+        };
+
+        // D (Sz^2 - S(S+1)/3) + E (Sx^2 - Sy^2)
+        for (e, (elec, op)) in elecs.iter().zip(ops.iter()).enumerate() {
+            let (s, d, zfs_e) = (elec.spin.val, elec.zfs_d.val, elec.zfs_e.val);
+            let mut zfs = eig::add(&eig::mul(&op[2], &op[2]), &eig::identity(op[2].len()), c(-s*(s+1.0)/3.0));
+            zfs = scale(&zfs, c(d));
+            zfs = eig::add(&zfs, &eig::mul(&op[0], &op[0]), c(zfs_e));
+            zfs = eig::add(&zfs, &eig::mul(&op[1], &op[1]), c(-zfs_e));
+            h0.extend(product(c(1.0), &[(e, &zfs)], &mults));
+        }
+
+        // Dipolar coupling d (S1.S2 - 3 S1z S2z), interspin vector along z
+        if elecs.len() == 2 && rad.dip_r.val > 0.0 {
+            let d = DIPOLAR/rad.dip_r.val.powi(3);
+            h0.extend(dot(d, (0, &ladders[0]), (1, &ladders[1]), &mults));
+            h0.extend(product(c(-3.0*d), &[(0, &ops[0][2]), (1, &ops[1][2])], &mults));
+        }
+
+        // Field direction and two perpendicular directions for the microwave field
+        let (st, ct, sf, cf) = (theta.sin(), theta.cos(), phi.sin(), phi.cos());
+        let gz = project(st*cf, st*sf, ct, &zeeman);
+        let b1 = vec![project(ct*cf, ct*sf, -st, &ones), project(-sf, cf, 0.0, &ones)];
+
+        SpinSystem::assemble(dim, h0, gz, b1)
+    }
+
+    // Dense blocks from the sparse operators
+    fn assemble(dim: usize, h0: Sparse, gz: Sparse, b1: Vec<Sparse>) -> SpinSystem {
+        let coupled = h0.iter().chain(gz.iter())
+            .filter(|&&(i, j, x)| i != j && x.norm() > 0.0)
+            .map(|&(i, j, _)| (i, j));
+        let states = eig::components(dim, coupled);
+
+        let mut block_of = vec![(0, 0); dim];  // (block, index in the block)
+        for (b, block) in states.iter().enumerate() {
+            for (l, &i) in block.iter().enumerate() { block_of[i] = (b, l); }
+        }
+
+        let mut blocks: Vec<Block> = states.iter()
+            .map(|block| Block { h0: eig::zeros(block.len()), gz: eig::zeros(block.len()) }).collect();
+        for &(i, j, x) in &h0 { blocks[block_of[i].0].h0[block_of[i].1][block_of[j].1] += x; }
+        for &(i, j, x) in &gz { blocks[block_of[i].0].gz[block_of[i].1][block_of[j].1] += x; }
+
+        // The components are Hermitian, so the elements with p <= q are enough
+        let mut links: BTreeMap<(usize, usize), Vec<Mat>> = BTreeMap::new();
+        for (o, op) in b1.iter().enumerate() {
+            for &(i, j, x) in op {
+                let ((p, li), (q, lj)) = (block_of[i], block_of[j]);
+                if p > q || x.norm() == 0.0 { continue }
+                let mats = links.entry((p, q)).or_insert_with(|| {
+                    vec![vec![vec![Complex64::new(0.0, 0.0); states[q].len()]; states[p].len()]; b1.len()]
+                });
+                mats[o][li][lj] += x;
+            }
+        }
+
+        SpinSystem {
+            blocks,
+            links: links.into_iter().map(|((p, q), ops)| Link { p, q, ops }).collect(),
+            n_b1: b1.len(),
+        }
+    }
+
+    // Field-swept resonances at the quantum hv (G), between fields lo and hi.
+    // Energies are sorted within every block, so every level pair (i, j) of two blocks
+    // coupled by the microwave field is followed along the field axis;
+    // sign changes of E_j - E_i - hv are located between the grid points (see refine).
+    // Stick intensities are |<i|S1|j>|^2 over the slope (field-swept spectrum),
+    // S1 being the electron spin along the microwave field.
+    pub fn resonances(&self, hv: f64, lo: f64, hi: f64) -> Vec<Stick> {
+        let fields: Vec<f64> = (0..=SEARCH_STEPS)
+            .map(|k| lo+(hi-lo)*k as f64/SEARCH_STEPS as f64).collect();
+        let grid: Vec<Vec<Levels>> = fields.iter()
+            .map(|&b| self.blocks.iter().map(|block| block.levels(b)).collect()).collect();
+
+        let mut sticks = Vec::new();
+        for link in &self.links {
+            let (p, q) = (link.p, link.q);
+            // Levels of different blocks aren't ordered: absorption either way
+            let signs: &[f64] = if p == q { &[1.0] } else { &[1.0, -1.0] };
+
+            let mut probs_a = self.probabilities(link, &grid[0][p].vecs, &grid[0][q].vecs);
+            for k in 0..SEARCH_STEPS {
+                let probs_b = self.probabilities(link, &grid[k+1][p].vecs, &grid[k+1][q].vecs);
+
+                for i in 0..self.blocks[p].h0.len() {
+                    for j in 0..self.blocks[q].h0.len() {
+                        if p == q && j <= i { continue }
+                        for &sign in signs {
+                            let tr = Transition { link, i, j, sign };
+                            let fa = tr.gap(&grid[k], hv);
+                            let fb = tr.gap(&grid[k+1], hv);
+                            if fa == 0.0 || fa.signum() == fb.signum() { continue }
+
+                            // Forbidden at both ends: not worth refining
+                            let (pa, pb) = (probs_a[i][j], probs_b[i][j]);
+                            if pa < PROB_MIN && pb < PROB_MIN { continue }
+
+                            if let Some(stick) = self.refine(&tr, hv, (fields[k], &grid[k], pa), (fields[k+1], &grid[k+1], pb)) {
+                                sticks.push(stick);
+                            }
+                        }
+                    }
+                }
+                probs_a = probs_b;
+            }
+        }
+
+        sticks
+    }
+
+    // Transition probabilities between all the eigenvectors of block p (columns of u)
+    // and of block q (of v): the moments are u^H op v
+    fn probabilities(&self, link: &Link, u: &Mat, v: &Mat) -> Vec<Vec<f64>> {
+        let (n_p, n_q) = (u.len(), v.len());
+        let zero = Complex64::new(0.0, 0.0);
+        let mut probs = vec![vec![0.0; n_q]; n_p];
+
+        for op in &link.ops {
+            let mut opv = vec![vec![zero; n_q]; n_p];
+            for (row, op_row) in opv.iter_mut().zip(op.iter()) {
+                for (b, &x) in op_row.iter().enumerate() {
+                    if x == zero { continue }
+                    for (y, &vb) in row.iter_mut().zip(v[b].iter()) { *y += x*vb; }
+                }
+            }
+
+            let mut moments = vec![vec![zero; n_q]; n_p];
+            for (a, u_row) in u.iter().enumerate() {
+                for (i, &ua) in u_row.iter().enumerate() {
+                    if ua == zero { continue }
+                    let ua = ua.conj();
+                    for (m, &y) in moments[i].iter_mut().zip(opv[a].iter()) { *m += ua*y; }
+                }
+            }
+
+            for (prob_row, m_row) in probs.iter_mut().zip(moments.iter()) {
+                for (prob, m) in prob_row.iter_mut().zip(m_row.iter()) { *prob += m.norm_sqr()/self.n_b1 as f64; }
+            }
+        }
+        probs
+    }
+
+    // Transition probability between the eigenvectors i of block p (columns of u) and j of block q (of v)
+    fn probability(&self, tr: &Transition, u: &Mat, v: &Mat) -> f64 {
+        tr.link.ops.iter().map(|op| {
+            let mut sum = Complex64::new(0.0, 0.0);
+            for (a, row) in op.iter().enumerate() {
+                let ua = u[a][tr.i].conj();
+                if ua == Complex64::new(0.0, 0.0) { continue }
+                for (b, x) in row.iter().enumerate() { sum += ua*x*v[b][tr.j]; }
+            }
+            sum.norm_sqr()
+        }).sum::<f64>()/self.n_b1 as f64
+    }
+
+    // The grid eigenvectors give the gap and its slope at both ends: the resonance is the root
+    // of their cubic Hermite interpolation, with the probability interpolated linearly.
+    // Where the slope changes appreciably (levels mixing with the field), Newton steps
+    // diagonalizing the two blocks take over.
+    fn refine(&self, tr: &Transition, hv: f64, (a, grid_a, pa): (f64, &[Levels], f64), (b, grid_b, pb): (f64, &[Levels], f64)) -> Option<Stick> {
+        let (fa, fb) = (tr.gap(grid_a, hv), tr.gap(grid_b, hv));
+        let (sa, sb) = (tr.slope(grid_a), tr.slope(grid_b));
+        let h = b-a;
+
+        let cubic = |t: f64| {
+            let (t2, t3) = (t*t, t*t*t);
+            (2.0*t3-3.0*t2+1.0)*fa+(t3-2.0*t2+t)*h*sa+(3.0*t2-2.0*t3)*fb+(t3-t2)*h*sb
+        };
+        let (mut lo, mut hi) = (0.0, 1.0);
+        for _ in 0..60 {
+            let mid = 0.5*(lo+hi);
+            if cubic(mid).signum() == fa.signum() { lo = mid; } else { hi = mid; }
+        }
+        let t = 0.5*(lo+hi);
+
+        if (sb-sa).abs() > MIXING*sa.abs().max(sb.abs()) {
+            return self.newton(tr, hv, (a, fa), b, a+t*h)
+        }
+
+        let slope = ((6.0*t*t-6.0*t)*(fa-fb)/h+(3.0*t*t-4.0*t+1.0)*sa+(3.0*t*t-2.0*t)*sb).abs();
+        let prob = (1.0-t)*pa+t*pb;
+        if prob < PROB_MIN || slope == 0.0 { return None }
+        Some(Stick { pos: a+t*h, int: prob/slope })
+    }
+
+    // Safeguarded Newton steps from field, the root bracketed in (a, b)
+    fn newton(&self, tr: &Transition, hv: f64, (mut a, fa): (f64, f64), mut b: f64, mut field: f64) -> Option<Stick> {
+        let (p, q) = (&self.blocks[tr.link.p], &self.blocks[tr.link.q]);
+
+        for _ in 0..100 {
+            let levels_p = p.levels(field);
+            let levels_q = if tr.link.p == tr.link.q { None } else { Some(q.levels(field)) };
+            let levels_q = levels_q.as_ref().unwrap_or(&levels_p);
+
+            let f = tr.sign*(levels_q.vals[tr.j]-levels_p.vals[tr.i])-hv;
+            let slope = tr.sign*(levels_q.slopes[tr.j]-levels_p.slopes[tr.i]);
+
+            if f.abs() < SEARCH_TOL || (b-a) < SEARCH_TOL {
+                let prob = self.probability(tr, &levels_p.vecs, &levels_q.vecs);
+                if prob < PROB_MIN || slope == 0.0 { return None }
+                return Some(Stick { pos: field, int: prob/slope.abs() })
+            }
+
+            // Keep the root bracketed
+            if f.signum() == fa.signum() { a = field; } else { b = field; }
+            let newton = field-f/slope;
+            field = if slope != 0.0 && newton > a && newton < b { newton } else { 0.5*(a+b) };
+        }
+
+        None
+    }
+}
+
+// Level i of block p and level j of block q; the absorbed quantum is sign (E_j - E_i)
+struct Transition<'a> {
+    link: &'a Link,
+    i: usize,
+    j: usize,
+    sign: f64,
+}
+
+impl Transition<'_> {
+    fn gap(&self, levels: &[Levels], hv: f64) -> f64 {
+        self.sign*(levels[self.link.q].vals[self.j]-levels[self.link.p].vals[self.i])-hv
+    }
+
+    fn slope(&self, levels: &[Levels]) -> f64 {
+        self.sign*(levels[self.link.q].slopes[self.j]-levels[self.link.p].slopes[self.i])
+    }
+}
+
+// Stick spectrum of a radical from the exact Hamiltonian (Exact or Powder engine), centered on
+// the free electron resonance at freq (GHz), searched over the sweep; total intensity is unitary
+pub fn sticks(rad: &Radical, engine: Engine, freq: f64, sweep: f64) -> Result<Vec<Stick>, HamError> {
+    check(rad, engine)?;
+    let b0 = center_field(freq);
+    let (lo, hi) = (b0-0.6*sweep, b0+0.6*sweep);
+
+    let mut sticks = match engine {
+        Engine::Powder => {
+            // Orientation grid over a quarter of the sphere, weighted by sin(theta)
+            let rhombic = rad.zfs_e.val != 0.0 || rad.partner.as_ref().is_some_and(|p| p.zfs_e.val != 0.0);
+            let n_phi = if rhombic { POWDER_PHI } else { 1 };
+            let mut sticks = Vec::new();
+
+            for t in 0..POWDER_THETA {
+                let theta = (t as f64+0.5)/POWDER_THETA as f64*PI/2.0;
+                for f in 0..n_phi {
+                    let phi = (f as f64+0.5)/n_phi as f64*PI/2.0;
+                    let sys = SpinSystem::oriented(rad, theta, phi, b0);
+                    for mut stick in sys.resonances(b0, lo, hi) {
+                        stick.int *= theta.sin();
+                        sticks.push(stick);
+                    }
+                }
+            }
+            stk::merge(sticks)
+        },
+        _ => SpinSystem::new(rad, b0).resonances(b0, lo, hi),
+    };
+
+    let total: f64 = sticks.iter().map(|s| s.int).sum();
+    for stick in sticks.iter_mut() {
+        stick.pos -= b0;
+        if total > 0.0 { stick.int /= total; }
+    }
+    Ok(sticks)
+}
+
+fn scale(m: &Mat, x: Complex64) -> Mat {
+    m.iter().map(|row| row.iter().map(|y| x*y).collect()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ent::{Nucleus};
+
+    fn radical(nucs: Vec<Nucleus>) -> Radical {
+        Radical::set(1.0, 0.0, 100.0, 0.0, nucs)
+    }
+
+    #[test]
+    fn nitrogen_second_order() {
+        let a = 15.0;
+        let rad = radical(vec![Nucleus::set(1.0, a, 1.0)]);
+        let b0 = center_field(9.5);
+        let mut sticks = sticks(&rad, Engine::Exact, 9.5, 100.0).unwrap();
+        sticks.sort_by(|x, y| x.pos.partial_cmp(&y.pos).unwrap());
+
+        // B_m = B0 - a m - a^2/(2 B0) (I(I+1) - m^2)
+        let expected = [-a-a*a/(2.0*b0), -a*a/b0, a-a*a/(2.0*b0)];
+        assert_eq!(sticks.len(), 3);
+        for (stick, pos) in sticks.iter().zip(expected.iter()) {
+            assert!((stick.pos-pos).abs() < 1E-3, "{} vs {}", stick.pos, pos);
+            assert!((stick.int-1.0/3.0).abs() < 1E-2);
+        }
+    }
+
+    #[test]
+    fn blocks_follow_perturbation() {
+        let nucs = vec![Nucleus::set(0.5, 3.0, 6.0), Nucleus::set(1.0, 15.0, 1.0)];
+        let first = stk::sticks(&nucs);
+        let exact = sticks(&radical(nucs), Engine::Exact, 9.5, 100.0).unwrap();
+
+        let total: f64 = exact.iter().map(|s| s.int).sum();
+        assert!((total-1.0).abs() < 1E-9);
+        for stick in &exact {
+            assert!(first.iter().any(|s| (s.pos-stick.pos).abs() < 0.2), "{}", stick.pos);
+        }
+    }
+
+    #[test]
+    fn too_large_is_an_error() {
+        let rad = radical(vec![Nucleus::set(0.5, 1.0, 16.0), Nucleus::set(1.0, 15.0, 1.0)]);
+        assert!(matches!(check(&rad, Engine::Exact), Err(HamError::TooLarge { .. })));
+        assert!(sticks(&rad, Engine::Exact, 9.5, 100.0).is_err());
+
+        // The same nuclei fit in the Mz blocks, but not as a whole
+        let rad = radical(vec![Nucleus::set(0.5, 1.0, 10.0)]);
+        assert_eq!(check(&rad, Engine::Exact), Ok(()));
+        assert_eq!(check(&rad, Engine::Powder), Err(HamError::TooLarge { dim: 2048, block: 2048 }));
+    }
+
+    #[test]
+    fn strong_exchange_halves_the_splitting() {
+        // Nitroxide biradical with J >> a: five lines a/2 apart, 1:2:3:2:1
+        let nitroxide = radical(vec![Nucleus::set(1.0, 15.0, 1.0)]);
+        let rad = Radical::biradical(nitroxide.clone(), nitroxide, 1000.0);
+        let sticks = sticks(&rad, Engine::Exact, 9.5, 100.0).unwrap();
+
+        let mut groups = [0.0; 5];
+        for stick in &sticks {
+            let k = (stick.pos/7.5).round()+2.0;
+            assert!((stick.pos-7.5*(k-2.0)).abs() < 0.5, "{}", stick.pos);
+            groups[k as usize] += stick.int;
+        }
+        for (int, expected) in groups.iter().zip([1.0, 2.0, 3.0, 2.0, 1.0].iter()) {
+            assert!((int-expected/9.0).abs() < 1E-2, "{:?}", groups);
+        }
+    }
+}
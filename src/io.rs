@@ -1,22 +1,500 @@
-pub fn get_from_asciistring(content: &str) -> Vec<f64> {
-    // let mut fld = Vec::new();  // field
-    let mut int = Vec::new();  // intensity
-
-    for line in content.lines() {
-        let line = line.trim();  // like Python's strip;
-        // TODO split_ascii_whitespace?
-        let cols = line.split_whitespace(); // like Python's split;
-
-        if cols.clone().count() == 3 {  // Can I replace the clone() solution?
-            for (idx, col) in cols.enumerate() {
-                match idx {
-                    // 1 => Some(fld.push(col.parse().unwrap())),
-                    2 => Some(int.push(col.parse().unwrap())),
-                    _ => None,
-                };
+use crate::flt::{Direction};
+use crate::jdx;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+// Acquisition parameters read along with the data
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Acquisition {
+    pub freq: Option<f64>,  // Microwave frequency (GHz)
+    pub power: Option<f64>,  // Microwave power (mW)
+    pub mod_amp: Option<f64>,  // Modulation amplitude (G)
+    pub temperature: Option<f64>,  // (K)
+    #[serde(default)]
+    pub time_const: Option<f64>,  // Lock-in time constant (ms)
+    #[serde(default)]
+    pub conv_time: Option<f64>,  // Conversion time per point (ms)
+    #[serde(default)]
+    pub direction: Option<Direction>,  // Sweep direction, lost once the points are sorted
+}
+
+// Spectrum on its field axis
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Spectrum {
+    pub fld: Vec<f64>,  // Field (G), ascending
+    pub int: Vec<f64>,  // Intensity
+    #[serde(default)]
+    pub acq: Acquisition,
+    #[serde(default)]
+    pub source: Option<PathBuf>,  // File it was read from
+}
+
+impl Spectrum {
+    // Points are sorted by field, so that spectra recorded downfield line up too
+    pub fn new(fld: Vec<f64>, int: Vec<f64>) -> Spectrum {
+        let mut pairs: Vec<(f64, f64)> = fld.into_iter().zip(int).collect();
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let (fld, int) = pairs.into_iter().unzip();
+        Spectrum { fld, int, acq: Acquisition::default(), source: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.int.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.int.is_empty()
+    }
+
+    pub fn center(&self) -> f64 {
+        match (self.fld.first(), self.fld.last()) {
+            (Some(first), Some(last)) => (first+last)/2.0,
+            _ => 0.0,
+        }
+    }
+
+    pub fn sweep(&self) -> f64 {
+        match (self.fld.first(), self.fld.last()) {
+            (Some(first), Some(last)) => last-first,
+            _ => 0.0,
+        }
+    }
+}
+
+// Import failure, with the line for text files
+#[derive(Debug)]
+pub enum ImportError {
+    Io(Error),
+    Number { line: usize, text: String },  // Not a number in a data line
+    NonFinite { line: usize, text: String },  // nan or inf
+    Column { line: usize, col: usize },  // Missing column
+    Slices { values: usize, points: usize },  // Binary data not made of whole spectra
+    NoData,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Io(error) => write!(f, "{}", error),
+            ImportError::Number { line, text } => write!(f, "line {}: \"{}\" is not a number", line, text),
+            ImportError::NonFinite { line, text } => write!(f, "line {}: \"{}\" is not a finite number", line, text),
+            ImportError::Column { line, col } => write!(f, "line {}: no column {}", line, col+1),
+            ImportError::Slices { values, points } => write!(f, "{} values don't make whole spectra of {} points", values, points),
+            ImportError::NoData => write!(f, "no data found"),
+        }
+    }
+}
+
+impl From<Error> for ImportError {
+    fn from(error: Error) -> Self { ImportError::Io(error) }
+}
+
+// Text format; None is detected from the content
+#[derive(Clone, Debug, Default)]
+pub struct AsciiFormat {
+    pub delimiter: Option<char>,  // Whitespace if None and no tab, semicolon or comma splits the data
+    pub comment: Option<String>,  // Besides the usual #, %, ! and //
+    pub fld_col: Option<usize>,  // 0-based; the second of three columns (index, field, intensity), else the first
+    pub int_col: Option<usize>,  // 0-based; the last of three columns, else the second
+    pub decimal_comma: Option<bool>,
+    pub fld_scale: Option<f64>,  // Field unit to G; from [mT], [T] or [G] in the header
+    pub skip: usize,  // Lines ignored at the top, before any header
+}
+
+const COMMENTS: [&str; 4] = ["#", "%", "!", "//"];
+
+// Text spectrum: comment lines are skipped anywhere, non-numeric lines before the data are the header
+pub fn read_ascii(content: &str, format: &AsciiFormat) -> Result<Spectrum, ImportError> {
+    let is_comment = |line: &str| {
+        COMMENTS.iter().any(|c| line.starts_with(c)) || format.comment.as_ref().is_some_and(|c| line.starts_with(c.as_str()))
+    };
+    let lines: Vec<(usize, &str)> = content.lines().enumerate().skip(format.skip)
+        .map(|(n, line)| (n+1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !is_comment(line))
+        .collect();
+
+    // Header: lines before the first one starting with a number
+    let starts_numeric = |line: &str| line.chars().next().is_some_and(|c| c.is_ascii_digit() || "+-.".contains(c));
+    let data_start = lines.iter().position(|(_, line)| starts_numeric(line)).ok_or(ImportError::NoData)?;
+    let header: String = lines[..data_start].iter().map(|(_, line)| *line).collect::<Vec<&str>>().join(" ");
+    let first = lines[data_start].1;
+
+    let delimiter = format.delimiter.or_else(|| detect_delimiter(first));
+    let decimal_comma = format.decimal_comma.unwrap_or(delimiter != Some(',') && first.contains(',') && !first.contains('.'));
+    let split = |line: &str| -> Vec<String> {
+        match delimiter {
+            Some(d) => line.split(d).map(|col| col.trim().to_string()).filter(|col| !col.is_empty()).collect(),
+            None => line.split_whitespace().map(String::from).collect(),
+        }
+    };
+
+    let n_cols = split(first).len();
+    let fld_col = format.fld_col.unwrap_or(if n_cols == 3 { 1 } else { 0 });
+    let int_col = format.int_col.unwrap_or(if n_cols == 3 { 2 } else { 1 });
+    let fld_scale = format.fld_scale.unwrap_or_else(|| unit_scale(&header));
+
+    let mut fld = Vec::new();
+    let mut int = Vec::new();
+    for (n, line) in &lines[data_start..] {
+        let cols = split(line);
+        let parse = |col: usize| -> Result<f64, ImportError> {
+            let text = cols.get(col).ok_or(ImportError::Column { line: *n, col })?;
+            let number = if decimal_comma { text.replace(',', ".") } else { text.clone() };
+            match number.parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(value),
+                Ok(_) => Err(ImportError::NonFinite { line: *n, text: text.clone() }),
+                Err(_) => Err(ImportError::Number { line: *n, text: text.clone() }),
             }
+        };
+        fld.push(parse(fld_col)?*fld_scale);
+        int.push(parse(int_col)?);
+    }
+
+    Ok(Spectrum::new(fld, int))
+}
+
+// Tab, semicolon or comma if they split the line, else whitespace (None)
+fn detect_delimiter(line: &str) -> Option<char> {
+    ['\t', ';'].iter().cloned().find(|d| line.contains(*d)).or_else(|| {
+        // A comma between numbers with decimal points, or alone between integers
+        let commas = line.matches(',').count();
+        let fields = line.split(',').filter(|f| !f.trim().is_empty()).count();
+        if commas > 0 && fields == commas+1 && (line.contains('.') || !line.contains(' ')) { Some(',') } else { None }
+    })
+}
+
+// Field unit from the header
+fn unit_scale(header: &str) -> f64 {
+    let header = header.replace('(', "[").replace(')', "]");
+    if header.contains("[mT]") { 10.0 } else if header.contains("[T]") { 1E4 } else { 1.0 }
+}
+
+// Open a spectrum, choosing the format from the extension
+pub fn open(path: &Path) -> Result<Spectrum, ImportError> {
+    open_with(path, &AsciiFormat::default())
+}
+
+// Same, with the format of text files
+pub fn open_with(path: &Path, format: &AsciiFormat) -> Result<Spectrum, ImportError> {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_uppercase();
+    let mut spectrum = match ext.as_str() {
+        "DSC" | "DTA" => read_bes3t(path)?.into_iter().next().ok_or(ImportError::NoData),
+        "PAR" | "SPC" => read_winepr(path)?.into_iter().next().ok_or(ImportError::NoData),
+        "JDX" | "DX" | "JCM" => jdx::from_jcamp(&fs::read_to_string(path)?),
+        _ => read_ascii(&fs::read_to_string(path)?, format),
+    }?;
+    spectrum.source = Some(path.to_path_buf());
+    Ok(spectrum)
+}
+
+// Every spectrum of a file: the slices of a 2D BES3T dataset, or the one spectrum of the others
+pub fn open_series(path: &Path) -> Result<Vec<Spectrum>, ImportError> {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_uppercase();
+    if ext != "DSC" && ext != "DTA" { return Ok(vec![open(path)?]) }
+
+    let mut spectra = read_bes3t(path)?;
+    for spectrum in spectra.iter_mut() { spectrum.source = Some(path.to_path_buf()); }
+    if spectra.is_empty() { Err(ImportError::NoData) } else { Ok(spectra) }
+}
+
+// Bruker BES3T .DSC descriptor and .DTA data pair (either path will do); one spectrum per slice of a 2D dataset.
+// Complex data keeps the real part.
+pub fn read_bes3t(path: &Path) -> Result<Vec<Spectrum>, Error> {
+    let desc = parse_dsc(&fs::read_to_string(sibling(path, "DSC"))?);
+    let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+    let get = |key: &str| desc.get(key).map(|val| val.as_str());
+    let number = |key: &str| -> Result<f64, Error> {
+        get(key).and_then(|val| first_word(val).parse().ok()).ok_or_else(|| invalid(format!("missing or bad {}", key)))
+    };
+
+    let complex = get("IKKF").is_some_and(|val| val.split(',').next().unwrap_or("").trim() == "CPLX");
+    let big_endian = get("BSEQ").map_or(true, |val| val.trim() != "LIT");
+    let format = get("IRFMT").map_or("D", |val| val.split(',').next().unwrap_or("D").trim());
+    let xpts = number("XPTS")? as usize;
+    let ypts = get("YPTS").and_then(|val| val.trim().parse().ok()).unwrap_or(1_usize).max(1);
+
+    // Data, slice after slice; complex values are interleaved
+    let data = decode(&fs::read(sibling(path, "DTA"))?, format, big_endian)
+        .ok_or_else(|| invalid(format!("unsupported IRFMT {}", format)))?;
+    let per_point = if complex { 2 } else { 1 };
+    if data.len() < xpts*ypts*per_point {
+        return Err(invalid(format!("{} values in the .DTA, {} expected", data.len(), xpts*ypts*per_point)))
+    }
+
+    let fld = axis(path, &desc, "X", xpts, big_endian)?;
+    let y = if ypts > 1 { axis(path, &desc, "Y", ypts, big_endian)? } else { Vec::new() };
+    let acq = acquisition(&desc);
+
+    // A power sweep has the power of every slice on the second axis
+    let power_scale = match get("YUNI").map(|val| val.trim_matches('\'')) {
+        Some("mW") => Some(1.0),
+        Some("W") => Some(1E3),
+        _ => None,
+    };
+
+    let mut spectra = Vec::with_capacity(ypts);
+    for (k, slice) in data[..xpts*ypts*per_point].chunks(xpts*per_point).enumerate() {
+        let re: Vec<f64> = slice.iter().step_by(per_point).cloned().collect();
+        let mut spectrum = Spectrum::new(fld.clone(), re);
+        spectrum.acq = acq.clone();
+        if let (Some(scale), Some(power)) = (power_scale, y.get(k)) { spectrum.acq.power = Some(power*scale); }
+        spectra.push(spectrum);
+    }
+
+    Ok(spectra)
+}
+
+// Same file with another extension, matching the case of the given one
+fn sibling(path: &Path, ext: &str) -> PathBuf {
+    let lower = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.chars().all(|c| c.is_lowercase()));
+    path.with_extension(if lower { ext.to_lowercase() } else { ext.to_string() })
+}
+
+// KEY value lines (.DSC and .par); '#' starts a section, '*' a comment
+fn parse_dsc(content: &str) -> HashMap<String, String> {
+    content.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('*'))
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let key = parts.next()?;
+            let val = parts.next().unwrap_or("").trim().trim_matches('\'');
+            Some((key.to_string(), val.to_string()))
+        })
+        .collect()
+}
+
+fn first_word(val: &str) -> &str {
+    val.split_whitespace().next().unwrap_or("")
+}
+
+// Binary values: D f64, F f32, I i32, S i16, C i8
+fn decode(bytes: &[u8], format: &str, big_endian: bool) -> Option<Vec<f64>> {
+    let size = match format { "D" => 8, "F" | "I" => 4, "S" => 2, "C" => 1, _ => return None };
+
+    Some(bytes.chunks_exact(size).map(|chunk| {
+        let mut buf = [0_u8; 8];
+        buf[..size].copy_from_slice(chunk);
+        if big_endian != cfg!(target_endian = "big") { buf[..size].reverse(); }
+        match format {
+            "D" => f64::from_ne_bytes(buf),
+            "F" => f32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            "I" => i32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            "S" => i16::from_ne_bytes([buf[0], buf[1]]) as f64,
+            _ => buf[0] as i8 as f64,
         }
+    }).collect())
+}
+
+// Axis from MIN/WID (linear) or from the companion file (.XGF, .YGF) for IGD axes; fields are brought to G
+fn axis(path: &Path, desc: &HashMap<String, String>, name: &str, pts: usize, big_endian: bool) -> Result<Vec<f64>, Error> {
+    let get = |key: &str| desc.get(&format!("{}{}", name, key)).map(|val| val.as_str());
+    let number = |key: &str| get(key).and_then(|val| first_word(val).parse::<f64>().ok());
+
+    let values = match get("TYP") {
+        Some("IGD") => {
+            let format = get("FMT").unwrap_or("D");
+            let bytes = fs::read(sibling(path, &format!("{}GF", name)))?;
+            let mut values = decode(&bytes, format, big_endian).unwrap_or_default();
+            values.truncate(pts);
+            values
+        },
+        _ => {
+            let (min, wid) = (number("MIN").unwrap_or(0.0), number("WID").unwrap_or(0.0));
+            (0..pts).map(|i| min+wid*i as f64/(pts.max(2)-1) as f64).collect()
+        },
+    };
+
+    let scale = match get("UNI").map(|val| val.trim_matches('\'')) {
+        Some("mT") => 10.0,
+        Some("T") => 1E4,
+        _ => 1.0,
     };
+    Ok(values.iter().map(|v| v*scale).collect())
+}
+
+// Value of a "number unit" keyword, converted by the unit
+fn with_unit(val: &str, units: &[(&str, f64)]) -> Option<f64> {
+    let mut words = val.split_whitespace();
+    let num: f64 = words.next()?.parse().ok()?;
+    let unit = words.next().unwrap_or("");
+    Some(num*units.iter().find(|(u, _)| *u == unit).map_or(1.0, |(_, factor)| *factor))
+}
+
+fn acquisition(desc: &HashMap<String, String>) -> Acquisition {
+    let get = |key: &str| desc.get(key).map(|val| val.as_str());
+    Acquisition {
+        freq: get("MWFQ").and_then(|val| with_unit(val, &[("GHz", 1E9), ("MHz", 1E6)])).map(|hz| hz*1E-9),
+        power: get("MWPW").and_then(|val| with_unit(val, &[("mW", 1E-3), ("uW", 1E-6)])).map(|w| w*1E3),
+        mod_amp: get("ModAmp").and_then(|val| with_unit(val, &[("G", 1.0), ("mT", 10.0)])),
+        temperature: get("Temperature").or_else(|| get("STMP")).and_then(|val| with_unit(val, &[("K", 1.0)])),
+        time_const: get("TimeConst").and_then(|val| with_unit(val, &[("ms", 1.0), ("s", 1E3), ("us", 1E-3)])),
+        conv_time: get("ConvTime").and_then(|val| with_unit(val, &[("ms", 1.0), ("s", 1E3), ("us", 1E-3)])),
+        direction: get("SweepDirection").map(|val| val.trim_matches('\'')).and_then(|val| match val {
+            "Up" => Some(Direction::Up),
+            "Down" => Some(Direction::Down),
+            _ => None,
+        }),
+    }
+}
+
+// JSS flags of ESP/WinEPR spectra
+const JSS_COMPLEX: u64 = 0x10;  // Each slice holds the real part, then the imaginary one
+const JSS_2D: u64 = 0x1000;  // Several slices
 
-    int
+// Bruker ESP/WinEPR .par parameters and .spc data (either path will do); one spectrum per slice.
+// WinEPR files (with a "DOS Format" key) hold little endian float32, ESP ones big endian int32.
+// Complex spectra keep their real part. Without JSS, every RES values make a slice.
+pub fn read_winepr(path: &Path) -> Result<Vec<Spectrum>, ImportError> {
+    let par = parse_dsc(&fs::read_to_string(sibling(path, "PAR"))?);
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+    let number = |key: &str| par.get(key).and_then(|val| first_word(val).parse::<f64>().ok());
+
+    let bytes = fs::read(sibling(path, "SPC"))?;
+    let data = match par.contains_key("DOS") {
+        true => decode(&bytes, "F", false),
+        false => decode(&bytes, "I", true),
+    }.unwrap_or_default();
+
+    let res = number("RES").or_else(|| number("SSX")).map_or(data.len(), |res| res as usize);
+    if res == 0 || data.len() < res { return Err(invalid("fewer values in the .spc than RES").into()) }
+
+    let jss = number("JSS").map(|jss| jss as u64);
+    let per_slice = if jss.is_some_and(|jss| jss & JSS_COMPLEX != 0) { 2*res } else { res };
+    if data.len() % per_slice != 0 { return Err(ImportError::Slices { values: data.len(), points: per_slice }) }
+    if jss.is_some_and(|jss| jss & JSS_2D == 0) && data.len() > per_slice {
+        return Err(invalid("several slices in a spectrum that JSS marks as 1D").into())
+    }
+
+    // Center and sweep (HCF, HSW), or start and width (GST, GSI; XXLB, XXWI)
+    let (start, width) = match (number("HCF"), number("HSW")) {
+        (Some(hcf), Some(hsw)) => (hcf-hsw/2.0, hsw),
+        _ => match (number("GST").or_else(|| number("XXLB")), number("GSI").or_else(|| number("XXWI"))) {
+            (Some(start), Some(width)) => (start, width),
+            _ => return Err(invalid("no field axis (HCF/HSW or GST/GSI)").into()),
+        },
+    };
+    let fld: Vec<f64> = (0..res).map(|i| start+width*i as f64/(res.max(2)-1) as f64).collect();
+
+    let acq = Acquisition {
+        freq: number("MF"),
+        power: number("MP"),
+        mod_amp: number("RMA"),
+        temperature: number("TE"),
+        time_const: number("RTC"),
+        conv_time: number("RCT"),
+        direction: None,
+    };
+
+    Ok(data.chunks_exact(per_slice).map(|slice| {
+        let mut spectrum = Spectrum::new(fld.clone(), slice[..res].to_vec());
+        spectrum.acq = acq.clone();
+        spectrum
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_settings_from_bes3t() {
+        let desc: HashMap<String, String> = [("ConvTime", "20.48 ms"), ("TimeConst", "0.01 s"), ("SweepDirection", "'Down'")]
+            .iter().map(|(key, val)| (key.to_string(), val.to_string())).collect();
+        let acq = acquisition(&desc);
+        assert_eq!(acq.conv_time, Some(20.48));
+        assert_eq!(acq.time_const, Some(10.0));
+        assert_eq!(acq.direction, Some(Direction::Down));
+    }
+
+    // Files of a test under the temporary directory
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gfactor-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn bes3t_power_sweep() {
+        // Complex data on a descending field axis (IGD, mT), two slices at 1 and 2 mW
+        let dir = scratch("bes3t");
+        fs::write(dir.join("sweep.DSC"), "#DESC\nBSEQ BIG\nIKKF CPLX\nIRFMT D\nXTYP IGD\nXFMT D\nXUNI 'mT'\nXPTS 4\n\
+            YTYP IDX\nYPTS 2\nYMIN 1.0\nYWID 1.0\nYUNI 'mW'\n#SPL\nMWFQ 9.5e9\n").unwrap();
+        let be = |values: &[f64]| -> Vec<u8> { values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect() };
+        fs::write(dir.join("sweep.XGF"), be(&[340.3, 340.2, 340.1, 340.0])).unwrap();
+        let data: Vec<f64> = (0..2).flat_map(|k| (0..4).flat_map(move |i| vec![(10*k+i) as f64, -1.0])).collect();
+        fs::write(dir.join("sweep.DTA"), be(&data)).unwrap();
+
+        let spectra = open_series(&dir.join("sweep.DTA")).unwrap();
+        assert_eq!(spectra.len(), 2);
+        assert!(spectra[0].fld.iter().zip(&[3400.0, 3401.0, 3402.0, 3403.0]).all(|(f, e)| (f-e).abs() < 1E-9));
+        assert_eq!(spectra[0].int, vec![3.0, 2.0, 1.0, 0.0]);
+        assert_eq!(spectra[1].int, vec![13.0, 12.0, 11.0, 10.0]);
+        assert_eq!((spectra[0].acq.power, spectra[1].acq.power), (Some(1.0), Some(2.0)));
+        assert_eq!(spectra[1].acq.freq, Some(9.5));
+        assert_eq!(open(&dir.join("sweep.DSC")).unwrap().int, spectra[0].int);
+    }
+
+    #[test]
+    fn winepr_and_esp() {
+        // WinEPR: little endian float32, center and sweep
+        let dir = scratch("winepr");
+        fs::write(dir.join("run.par"), "DOS  Format\nRES 5\nHCF 3400\nHSW 40\nMF 9.52\nRCT 81.92\nRTC 20.48\n").unwrap();
+        let data: Vec<u8> = [0.0_f32, 1.0, 4.0, 9.0, 16.0].iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        fs::write(dir.join("run.spc"), data).unwrap();
+        let spectrum = open(&dir.join("run.spc")).unwrap();
+        assert_eq!(spectrum.fld, vec![3380.0, 3390.0, 3400.0, 3410.0, 3420.0]);
+        assert_eq!(spectrum.int, vec![0.0, 1.0, 4.0, 9.0, 16.0]);
+        assert_eq!((spectrum.acq.freq, spectrum.acq.conv_time, spectrum.acq.time_const), (Some(9.52), Some(81.92), Some(20.48)));
+
+        // ESP: big endian int32, start and width, two slices
+        fs::write(dir.join("esp.PAR"), "RES 3\nGST 3300\nGSI 100\n").unwrap();
+        let data: Vec<u8> = [1_i32, -2, 3, 4, 5, -6].iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+        fs::write(dir.join("esp.SPC"), data).unwrap();
+        let slices = read_winepr(&dir.join("esp.PAR")).unwrap();
+        assert_eq!(slices.len(), 2);
+        assert_eq!(slices[1].fld, vec![3300.0, 3350.0, 3400.0]);
+        assert_eq!(slices[1].int, vec![4.0, 5.0, -6.0]);
+
+        // JSS: the real part of a complex spectrum, 1D and 2D
+        fs::write(dir.join("esp.PAR"), "JSS 16\nRES 3\nGST 3300\nGSI 100\n").unwrap();
+        let slices = read_winepr(&dir.join("esp.PAR")).unwrap();
+        assert_eq!(slices.len(), 1);
+        assert_eq!(slices[0].int, vec![1.0, -2.0, 3.0]);
+        fs::write(dir.join("esp.PAR"), "JSS 4096\nRES 3\nGST 3300\nGSI 100\n").unwrap();
+        assert_eq!(read_winepr(&dir.join("esp.PAR")).unwrap().len(), 2);
+        fs::write(dir.join("esp.PAR"), "JSS 0\nRES 3\nGST 3300\nGSI 100\n").unwrap();
+        assert!(matches!(read_winepr(&dir.join("esp.PAR")), Err(ImportError::Io(_))));
+
+        // Values left over after the last whole slice
+        fs::write(dir.join("esp.PAR"), "RES 4\nGST 3300\nGSI 100\n").unwrap();
+        assert!(matches!(read_winepr(&dir.join("esp.PAR")), Err(ImportError::Slices { values: 6, points: 4 })));
+    }
+
+    #[test]
+    fn ascii_options() {
+        // Detected: header unit, semicolons and decimal commas
+        let spectrum = read_ascii("Field [mT];Intensity\n340,2;1,5\n340,1;-2\n", &AsciiFormat::default()).unwrap();
+        assert_eq!((spectrum.fld, spectrum.int), (vec![3401.0, 3402.0], vec![-2.0, 1.5]));
+
+        // Given: a preamble to skip, then columns picked out of four
+        let content = "Instrument 7 lines 4 columns\nx y z w\n1 3400 5 6\n2 3410 7 8\n";
+        let format = AsciiFormat { skip: 1, fld_col: Some(1), int_col: Some(3), delimiter: Some(' '), ..AsciiFormat::default() };
+        let spectrum = read_ascii(content, &format).unwrap();
+        assert_eq!((spectrum.fld, spectrum.int), (vec![3400.0, 3410.0], vec![6.0, 8.0]));
+
+        // Errors carry the line
+        match read_ascii("3400 1\nnan 2\n", &AsciiFormat::default()) {
+            Err(ImportError::NonFinite { line: 2, .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match read_ascii("3400 1\n3401 x\n", &AsciiFormat::default()) {
+            Err(ImportError::Number { line: 2, .. }) => (),
+            other => panic!("{:?}", other),
+        }
+    }
 }
@@ -0,0 +1,122 @@
+// Isotope library
+#[derive(Clone, Copy, Debug)]
+pub struct Isotope {
+    pub symbol: &'static str,  // Mass number + element, e.g. "14N"
+    pub element: &'static str,
+    pub spin: f64,  // Nuclear spin
+    pub gamma: f64,  // Gyromagnetic ratio / 2pi (MHz/T)
+    pub abund: f64,  // Natural abundance (%)
+}
+
+const fn iso(symbol: &'static str, element: &'static str, spin: f64, gamma: f64, abund: f64) -> Isotope {
+    Isotope { symbol, element, spin, gamma, abund }
+}
+
+pub const ISOTOPES: [Isotope; 74] = [
+    iso("1H", "H", 0.5, 42.577478, 99.9885),
+    iso("2H", "H", 1.0, 6.535903, 0.0115),
+    iso("6Li", "Li", 1.0, 6.265350, 7.59),
+    iso("7Li", "Li", 1.5, 16.548013, 92.41),
+    iso("10B", "B", 3.0, 4.575241, 19.9),
+    iso("11B", "B", 1.5, 13.662979, 80.1),
+    iso("12C", "C", 0.0, 0.0, 98.93),
+    iso("13C", "C", 0.5, 10.708395, 1.07),
+    iso("14N", "N", 1.0, 3.077706, 99.636),
+    iso("15N", "N", 0.5, -4.316267, 0.364),
+    iso("16O", "O", 0.0, 0.0, 99.757),
+    iso("17O", "O", 2.5, -5.774235, 0.038),
+    iso("18O", "O", 0.0, 0.0, 0.205),
+    iso("19F", "F", 0.5, 40.077577, 100.0),
+    iso("23Na", "Na", 1.5, 11.268860, 100.0),
+    iso("24Mg", "Mg", 0.0, 0.0, 78.99),
+    iso("25Mg", "Mg", 2.5, -2.608373, 10.00),
+    iso("26Mg", "Mg", 0.0, 0.0, 11.01),
+    iso("27Al", "Al", 2.5, 11.103080, 100.0),
+    iso("28Si", "Si", 0.0, 0.0, 92.223),
+    iso("29Si", "Si", 0.5, -8.465498, 4.685),
+    iso("30Si", "Si", 0.0, 0.0, 3.092),
+    iso("31P", "P", 0.5, 17.251631, 100.0),
+    iso("32S", "S", 0.0, 0.0, 94.99),
+    iso("33S", "S", 1.5, 3.271654, 0.75),
+    iso("34S", "S", 0.0, 0.0, 4.25),
+    iso("36S", "S", 0.0, 0.0, 0.01),
+    iso("35Cl", "Cl", 1.5, 4.176541, 75.76),
+    iso("37Cl", "Cl", 1.5, 3.476553, 24.24),
+    iso("39K", "K", 1.5, 1.989316, 93.2581),
+    iso("40K", "K", 4.0, -2.473700, 0.0117),
+    iso("41K", "K", 1.5, 1.091864, 6.7302),
+    iso("50V", "V", 6.0, 4.250482, 0.250),
+    iso("51V", "V", 3.5, 11.213310, 99.750),
+    iso("50Cr", "Cr", 0.0, 0.0, 4.345),
+    iso("52Cr", "Cr", 0.0, 0.0, 83.789),
+    iso("53Cr", "Cr", 1.5, -2.411529, 9.501),
+    iso("54Cr", "Cr", 0.0, 0.0, 2.365),
+    iso("55Mn", "Mn", 2.5, 10.576393, 100.0),
+    iso("54Fe", "Fe", 0.0, 0.0, 5.845),
+    iso("56Fe", "Fe", 0.0, 0.0, 91.754),
+    iso("57Fe", "Fe", 0.5, 1.381578, 2.119),
+    iso("58Fe", "Fe", 0.0, 0.0, 0.282),
+    iso("59Co", "Co", 3.5, 10.077, 100.0),
+    iso("58Ni", "Ni", 0.0, 0.0, 68.077),
+    iso("60Ni", "Ni", 0.0, 0.0, 26.223),
+    iso("61Ni", "Ni", 1.5, -3.811400, 1.1399),
+    iso("62Ni", "Ni", 0.0, 0.0, 3.6346),
+    iso("64Ni", "Ni", 0.0, 0.0, 0.9255),
+    iso("63Cu", "Cu", 1.5, 11.298187, 69.15),
+    iso("65Cu", "Cu", 1.5, 12.103012, 30.85),
+    iso("64Zn", "Zn", 0.0, 0.0, 49.17),
+    iso("66Zn", "Zn", 0.0, 0.0, 27.73),
+    iso("67Zn", "Zn", 2.5, 2.668863, 4.10),
+    iso("68Zn", "Zn", 0.0, 0.0, 18.45),
+    iso("70Zn", "Zn", 0.0, 0.0, 0.61),
+    iso("79Br", "Br", 1.5, 10.703955, 50.69),
+    iso("81Br", "Br", 1.5, 11.538156, 49.31),
+    iso("92Mo", "Mo", 0.0, 0.0, 14.53),
+    iso("94Mo", "Mo", 0.0, 0.0, 9.15),
+    iso("95Mo", "Mo", 2.5, -2.787366, 15.84),
+    iso("96Mo", "Mo", 0.0, 0.0, 16.67),
+    iso("97Mo", "Mo", 2.5, -2.846075, 9.60),
+    iso("98Mo", "Mo", 0.0, 0.0, 24.39),
+    iso("100Mo", "Mo", 0.0, 0.0, 9.82),
+    iso("107Ag", "Ag", 0.5, -1.733043, 51.839),
+    iso("109Ag", "Ag", 0.5, -1.992438, 48.161),
+    iso("127I", "I", 2.5, 8.578520, 100.0),
+    iso("190Pt", "Pt", 0.0, 0.0, 0.01),
+    iso("192Pt", "Pt", 0.0, 0.0, 0.78),
+    iso("194Pt", "Pt", 0.0, 0.0, 32.86),
+    iso("195Pt", "Pt", 0.5, 9.292080, 33.78),
+    iso("196Pt", "Pt", 0.0, 0.0, 25.21),
+    iso("198Pt", "Pt", 0.0, 0.0, 7.36),
+];
+
+// Look up an isotope by symbol ("14N")
+pub fn get(symbol: &str) -> Option<Isotope> {
+    ISOTOPES.iter().find(|iso| iso.symbol == symbol).copied()
+}
+
+// All the isotopes of an element ("Cu")
+pub fn element(element: &str) -> Vec<Isotope> {
+    ISOTOPES.iter().filter(|iso| iso.element == element).copied().collect()
+}
+
+// Most abundant magnetic isotope of an element
+pub fn main_magnetic(element: &str) -> Option<Isotope> {
+    self::element(element).into_iter()
+        .filter(|iso| iso.spin > 0.0)
+        .fold(None, |best: Option<Isotope>, iso| match best {
+            Some(best) if best.abund >= iso.abund => Some(best),
+            _ => Some(iso),
+        })
+}
+
+// Natural abundances (fractions) of the isotopes of an element
+pub fn natural_abund(element: &str) -> Vec<(String, f64)> {
+    self::element(element).iter().map(|iso| (String::from(iso.symbol), 0.01*iso.abund)).collect()
+}
+
+// Scale a coupling measured on one isotope to another one (e.g. 1H -> 2H, 14N -> 15N)
+pub fn convert_hpf(hpf: f64, from: &str, to: &str) -> Option<f64> {
+    let (from, to) = (get(from)?, get(to)?);
+    if from.gamma == 0.0 { return None }
+    Some(hpf*to.gamma/from.gamma)
+}
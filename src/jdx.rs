@@ -0,0 +1,296 @@
+// JCAMP-DX spectra: XYDATA=(X++(Y..Y)) in AFFN or ASDF (SQZ, DIF, DUP) form, XYPOINTS=(XY..XY)
+use crate::io::{Acquisition, ImportError, Spectrum};
+use std::collections::HashMap;
+
+// Encoding of the exported ordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Affn,  // Plain numbers
+    DifDup,  // Differences, compressed
+}
+
+const LINE_LEN: usize = 80;
+
+// Read the first spectrum of a JCAMP-DX file
+pub fn from_jcamp(content: &str) -> Result<Spectrum, ImportError> {
+    let mut labels: HashMap<String, String> = HashMap::new();
+    let mut table: Option<String> = None;  // Form of the data table
+    let mut data: Vec<(usize, &str)> = Vec::new();
+
+    for (n, line) in content.lines().enumerate() {
+        let line = line.split("$$").next().unwrap_or("").trim();  // $$ starts a comment
+        if let Some(record) = line.strip_prefix("##") {
+            if table.is_some() && !data.is_empty() { break }  // Only the first block
+            let mut parts = record.splitn(2, '=');
+            let label = normalize(parts.next().unwrap_or(""));
+            let value = parts.next().unwrap_or("").trim().to_string();
+            if label == "XYDATA" || label == "XYPOINTS" { table = Some(value.replace(' ', "")); }
+            labels.insert(label, value);
+        } else if table.is_some() && !line.is_empty() {
+            data.push((n+1, line));
+        }
+    }
+
+    let form = table.ok_or(ImportError::NoData)?;
+    let number = |label: &str| labels.get(label).and_then(|val| val.split_whitespace().next()?.parse::<f64>().ok());
+    let xfactor = number("XFACTOR").unwrap_or(1.0);
+    let yfactor = number("YFACTOR").unwrap_or(1.0);
+
+    // Abscissae and ordinates, line by line
+    let (mut xs, mut ys) = (Vec::new(), Vec::new());
+    let mut lines: Vec<(f64, usize)> = Vec::new();  // Abscissa and ordinates of each line
+    let mut check = false;  // The previous line ended in DIF form: its last ordinate is repeated
+    for (n, line) in data {
+        let (values, dif) = decode(line).ok_or(ImportError::Number { line: n, text: line.to_string() })?;
+        if values.is_empty() { continue }
+
+        if form.starts_with("(XY..XY)") {
+            for pair in values.chunks_exact(2) { xs.push(pair[0]*xfactor); ys.push(pair[1]*yfactor); }
+            continue
+        }
+        let mut line_ys = values[1..].to_vec();
+        if check && !line_ys.is_empty() { line_ys.remove(0); }
+        check = dif;
+        lines.push((values[0]*xfactor, line_ys.len()));
+        ys.extend(line_ys.iter().map(|y| y*yfactor));
+    }
+    if ys.is_empty() { return Err(ImportError::NoData) }
+
+    // Equally spaced abscissae from FIRSTX, LASTX
+    let fld: Vec<f64> = match (number("FIRSTX"), number("LASTX")) {
+        (Some(first), Some(last)) if !form.starts_with("(XY..XY)") => {
+            let n = ys.len();
+            (0..n).map(|i| first+(last-first)*i as f64/(n.max(2)-1) as f64).collect()
+        },
+        _ if !form.starts_with("(XY..XY)") => {
+            // Each line spaced up to the next one, the last one as DELTAX or the previous step
+            let mut step = number("DELTAX").map(|dx| dx*xfactor).unwrap_or(0.0);
+            for (k, &(x, count)) in lines.iter().enumerate() {
+                if let Some(&(next, _)) = lines.get(k+1) { if count > 0 { step = (next-x)/count as f64; } }
+                xs.extend((0..count).map(|i| x+i as f64*step));
+            }
+            xs
+        },
+        _ => xs,
+    };
+
+    let scale = match labels.get("XUNITS").map(|unit| unit.to_uppercase()) {
+        Some(ref unit) if unit == "MT" => 10.0,
+        Some(ref unit) if unit == "T" || unit == "TESLA" => 1E4,
+        _ => 1.0,
+    };
+
+    let mut spectrum = Spectrum::new(fld.iter().map(|x| x*scale).collect(), ys);
+    spectrum.acq = Acquisition {
+        freq: number(".OBSERVEFREQUENCY").map(|mhz| mhz*1E-3).or_else(|| number(".MICROWAVEFREQUENCY").map(|f| match f {
+            f if f > 1E6 => f*1E-9,  // Hz
+            f if f > 1E3 => f*1E-3,  // MHz
+            f => f,  // GHz
+        })),
+        power: number(".MICROWAVEPOWER"),
+        mod_amp: number(".MODULATIONAMPLITUDE").map(|amp| amp*scale),
+        temperature: number("TEMPERATURE").or_else(|| number(".TEMPERATURE")),
+        ..Acquisition::default()
+    };
+    Ok(spectrum)
+}
+
+// Labels compare without spaces, dashes, slashes and underscores
+fn normalize(label: &str) -> String {
+    label.chars().filter(|c| !" -/_".contains(*c)).collect::<String>().to_uppercase()
+}
+
+// Values of a data line, and whether it ends in DIF form
+fn decode(line: &str) -> Option<(Vec<f64>, bool)> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Kind { Affn, Sqz, Dif, Dup }
+
+    // Tokens: a kind and the digits of the value
+    let mut tokens: Vec<(Kind, String)> = Vec::new();
+    let asdf = line.chars().any(|c| c.is_ascii_alphabetic() && c != 'E' && c != 'e' || c == '@' || c == '%');
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let new = match c {
+            ' ' | ',' | ';' | '\t' => None,
+            '0'..='9' | '.' => match tokens.last_mut() {
+                Some((_, digits)) if !digits.is_empty() && !digits.ends_with(' ') => { digits.push(c); continue },
+                _ => Some((Kind::Affn, c.to_string())),
+            },
+            '+' | '-' => match tokens.last_mut() {
+                Some((Kind::Affn, digits)) if digits.ends_with('E') || digits.ends_with('e') => { digits.push(c); continue },
+                _ => Some((Kind::Affn, c.to_string())),
+            },
+            // Exponent of a plain number, otherwise SQZ: E5 alone is ambiguous in compressed lines
+            'E' | 'e' if chars.peek().is_some_and(|n| *n == '+' || *n == '-' || n.is_ascii_digit() && !asdf)
+                && matches!(tokens.last(), Some((Kind::Affn, d)) if !d.ends_with(' ')) => {
+                tokens.last_mut()?.1.push(c);
+                continue
+            },
+            '@' => Some((Kind::Sqz, "0".to_string())),
+            'A'..='I' => Some((Kind::Sqz, (c as u8-b'A'+1).to_string())),
+            'a'..='i' => Some((Kind::Sqz, format!("-{}", c as u8-b'a'+1))),
+            '%' => Some((Kind::Dif, "0".to_string())),
+            'J'..='R' => Some((Kind::Dif, (c as u8-b'J'+1).to_string())),
+            'j'..='r' => Some((Kind::Dif, format!("-{}", c as u8-b'j'+1))),
+            'S'..='Z' => Some((Kind::Dup, (c as u8-b'S'+1).to_string())),
+            's' => Some((Kind::Dup, "9".to_string())),
+            '?' => Some((Kind::Affn, "NaN".to_string())),
+            _ => return None,
+        };
+        // Separators close the current token
+        match new {
+            Some(token) => tokens.push(token),
+            None => if let Some((_, digits)) = tokens.last_mut() { digits.push(' ') },
+        }
+    }
+
+    let mut values: Vec<f64> = Vec::new();
+    let mut last_dif: Option<f64> = None;
+    let mut ends_dif = false;
+    for (kind, digits) in tokens {
+        let value: f64 = digits.trim().parse().ok()?;
+        match kind {
+            Kind::Affn | Kind::Sqz => { values.push(value); last_dif = None; ends_dif = false },
+            Kind::Dif => {
+                let prev = *values.last()?;
+                values.push(prev+value);
+                last_dif = Some(value);
+                ends_dif = true;
+            },
+            Kind::Dup => {
+                let prev = *values.last()?;
+                for _ in 1..value as usize {
+                    let next = match last_dif { Some(dif) => values.last()?+dif, None => prev };
+                    values.push(next);
+                }
+            },
+        }
+    }
+    Some((values, ends_dif))
+}
+
+// Spectrum as JCAMP-DX, fields in G
+pub fn to_jcamp(spectrum: &Spectrum, title: &str, encoding: Encoding) -> String {
+    let n = spectrum.len();
+    let (first, last) = (spectrum.fld.first().cloned().unwrap_or(0.0), spectrum.fld.last().cloned().unwrap_or(0.0));
+    let delta = if n > 1 { (last-first)/(n-1) as f64 } else { 0.0 };
+
+    // Ordinates as integers with YFACTOR
+    let max = spectrum.int.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+    let yfactor = if max > 0.0 { max/1E6 } else { 1.0 };
+    let ints: Vec<i64> = spectrum.int.iter().map(|y| (y/yfactor).round() as i64).collect();
+
+    let mut out = format!("##TITLE={}\n##JCAMP-DX=5.01\n##DATA TYPE=EPR SPECTRUM\n##ORIGIN=g-factor\n##OWNER=\n", title);
+    out += "##XUNITS=GAUSS\n##YUNITS=ARBITRARY UNITS\n";
+    if let Some(freq) = spectrum.acq.freq { out += &format!("##.OBSERVE FREQUENCY={}\n##.MICROWAVE FREQUENCY={}\n", freq*1E3, freq*1E9); }
+    if let Some(power) = spectrum.acq.power { out += &format!("##.MICROWAVE POWER={}\n", power); }
+    if let Some(mod_amp) = spectrum.acq.mod_amp { out += &format!("##.MODULATION AMPLITUDE={}\n", mod_amp); }
+    if let Some(temperature) = spectrum.acq.temperature { out += &format!("##TEMPERATURE={}\n", temperature); }
+    out += &format!("##.CENTER FIELD={}\n##.SWEEP WIDTH={}\n", (first+last)/2.0, last-first);
+    out += &format!("##FIRSTX={}\n##LASTX={}\n##DELTAX={}\n##XFACTOR=1\n##YFACTOR={:e}\n", first, last, delta, yfactor);
+    out += &format!("##FIRSTY={}\n##NPOINTS={}\n##XYDATA=(X++(Y..Y))\n", ints.first().map_or(0.0, |y| *y as f64*yfactor), n);
+
+    match encoding {
+        Encoding::Affn => {
+            for (k, chunk) in ints.chunks(10).enumerate() {
+                let x = first+(k*10) as f64*delta;
+                let ys: Vec<String> = chunk.iter().map(|y| y.to_string()).collect();
+                out += &format!("{} {}\n", x, ys.join(" "));
+            }
+        },
+        Encoding::DifDup => {
+            let mut i = 0;
+            while i < n {
+                // Abscissa, first ordinate (SQZ), then differences (DIF, DUP) until the line is full;
+                // the last ordinate of a line starts the next one as the Y check
+                let mut line = format!("{}{}", first+i as f64*delta, sqz(ints[i]));
+                let mut j = i+1;
+                while j < n && line.len() < LINE_LEN-12 {
+                    let dif = ints[j]-ints[j-1];
+                    let mut count = 1;
+                    while j+count < n && ints[j+count]-ints[j+count-1] == dif { count += 1; }
+                    line += &dif_str(dif);
+                    if count > 1 { line += &dup(count); }
+                    j += count;
+                }
+                out += &line;
+                out += "\n";
+                if j >= n { break }
+                i = if j-1 > i { j-1 } else { j };
+            }
+        },
+    }
+
+    out += "##END=\n";
+    out
+}
+
+// Leading digit as a letter: @, A-I positive, a-i negative
+fn sqz(value: i64) -> String {
+    pseudo(value, ['@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I'], ['@', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i'])
+}
+
+fn dif_str(value: i64) -> String {
+    pseudo(value, ['%', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R'], ['%', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r'])
+}
+
+fn pseudo(value: i64, pos: [char; 10], neg: [char; 10]) -> String {
+    let digits = value.abs().to_string();
+    let lead = digits.as_bytes()[0]-b'0';
+    let letter = if value < 0 { neg[lead as usize] } else { pos[lead as usize] };
+    format!("{}{}", letter, &digits[1..])
+}
+
+// Repeat count: S-Z for 1-8, s for 9
+fn dup(count: usize) -> String {
+    let digits = count.to_string();
+    let lead = digits.as_bytes()[0]-b'0';
+    let letter = ['?', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 's'][lead as usize];
+    format!("{}{}", letter, &digits[1..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(data: &str) -> String {
+        format!("##TITLE=example\n##JCAMP-DX=5.01\n##XUNITS=GAUSS\n##FIRSTX=3400\n##LASTX=3409\n##XFACTOR=1\n##YFACTOR=1\n\
+            ##NPOINTS=10\n##XYDATA=(X++(Y..Y))\n{}##END=\n", data)
+    }
+
+    #[test]
+    fn specification_examples() {
+        // 1 2 3 3 2 1 0 -1 -2 -3 in every form of the JCAMP-DX specification
+        let expected: Vec<f64> = vec![1.0, 2.0, 3.0, 3.0, 2.0, 1.0, 0.0, -1.0, -2.0, -3.0];
+        for data in [
+            "3400 1 2 3 3 2 1 0 -1 -2 -3\n",  // AFFN
+            "3400 1+2+3+3+2+1+0-1-2-3\n",  // PAC
+            "3400 1BCCBA@abc\n",  // SQZ
+            "3400 1JJ%jjjjjj\n",  // DIF
+            "3400 1JT%jX\n",  // DIFDUP
+            "3400 1JT%j\n3404 2jW\n",  // DIFDUP over two lines, with the Y check
+        ].iter() {
+            let spectrum = from_jcamp(&table(data)).unwrap();
+            assert_eq!(spectrum.int, expected, "{}", data);
+            assert_eq!(spectrum.fld, (0..10).map(|i| 3400.0+i as f64).collect::<Vec<f64>>());
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let fld: Vec<f64> = (0..257).map(|i| 3300.0+0.5*i as f64).collect();
+        let int: Vec<f64> = fld.iter().map(|f| { let a = (f-3364.0)/3.0; -a*(-a*a).exp()+0.01 }).collect();
+        let mut spectrum = Spectrum::new(fld, int);
+        spectrum.acq = Acquisition { freq: Some(9.43), power: Some(2.0), mod_amp: Some(0.8), temperature: Some(295.0), ..Acquisition::default() };
+
+        for encoding in [Encoding::DifDup, Encoding::Affn].iter() {
+            let back = from_jcamp(&to_jcamp(&spectrum, "round trip", *encoding)).unwrap();
+            assert_eq!(back.len(), spectrum.len());
+            assert!(back.fld.iter().zip(&spectrum.fld).all(|(b, f)| (b-f).abs() < 1E-9));
+            let max = spectrum.int.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+            assert!(back.int.iter().zip(&spectrum.int).all(|(b, y)| (b-y).abs() <= max*1E-6), "{:?}", encoding);
+            assert_eq!((back.acq.power, back.acq.mod_amp, back.acq.temperature), (Some(2.0), Some(0.8), Some(295.0)));
+            assert!((back.acq.freq.unwrap()-9.43).abs() < 1E-12);
+        }
+    }
+}
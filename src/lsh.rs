@@ -0,0 +1,319 @@
+use num_complex::Complex64;
+use serde::{Serialize, Deserialize};
+use std::f64::consts::PI;
+
+// Spectrum output mode
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Mode {
+    Absorption,
+    FirstDerivative,
+    SecondDerivative,
+    Dispersion,  // Out-of-phase absorption
+}
+
+impl Mode {
+    // Lock-in harmonic of the mode
+    pub fn harmonic(&self) -> usize {
+        match self {
+            Mode::Absorption | Mode::Dispersion => 0,
+            Mode::FirstDerivative => 1,
+            Mode::SecondDerivative => 2,
+        }
+    }
+
+    // Detection phase of the mode (degrees)
+    pub fn phase(&self) -> f64 {
+        match self {
+            Mode::Dispersion => 90.0,
+            _ => 0.0,
+        }
+    }
+
+    // Mix absorption and dispersion at the detection phase (mode + phase error, degrees)
+    pub fn mix(&self, (abs, disp): (f64, f64), phase: f64) -> f64 {
+        let phi = (self.phase()+phase).to_radians();
+        abs*phi.cos() + disp*phi.sin()
+    }
+}
+
+// Lineshape model of a radical
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum Shape {
+    #[default]
+    PseudoVoigt,  // Lorentzian/Gaussian sum, weighted by lrtz
+    Voigt,  // Lorentzian/Gaussian convolution
+}
+
+// Lineshape functions
+// Every shape has unitary area; lw is the peak-to-peak width of the first derivative;
+// deriv is the derivative order (0: absorption, 1: first derivative, 2: second derivative).
+// Each function returns the (absorption, dispersion) couple.
+
+pub fn lorentzian(a: f64, lw: f64, deriv: usize) -> (f64, f64) {
+    let t2 = 2.0/(3.0_f64.sqrt()*lw);  // 1/HWHM
+    let ta = t2*a;
+    let den = 1.0+ta.powi(2);
+
+    match deriv {
+        0 => (t2/(PI*den), t2*ta/(PI*den)),
+        1 => (-2.0*t2.powi(2)*ta/(PI*den.powi(2)), t2.powi(2)*(1.0-ta.powi(2))/(PI*den.powi(2))),
+        _ => (
+            2.0*t2.powi(3)*(3.0*ta.powi(2)-1.0)/(PI*den.powi(3)),
+            -2.0*t2.powi(3)*ta*(3.0-ta.powi(2))/(PI*den.powi(3)),
+        ),
+    }
+}
+
+pub fn gaussian(a: f64, lw: f64, deriv: usize) -> (f64, f64) {
+    let t2 = 2.0/lw;  // 1/sigma
+    let x = a*t2/2.0_f64.sqrt();
+    let norm = t2/(2.0*PI).sqrt();
+    let dd = (-x.powi(2)).exp();  // Re w(x)
+    let ff = 2.0/PI.sqrt()*dawson(x);  // Im w(x)
+    let k = t2/2.0_f64.sqrt();  // dx/da
+
+    // Derivatives of the Faddeeva function w(x) on the real axis
+    match deriv {
+        0 => (norm*dd, norm*ff),
+        1 => (
+            norm*k*(-2.0*x*dd),
+            norm*k*(-2.0*x*ff+2.0/PI.sqrt()),
+        ),
+        _ => (
+            norm*k.powi(2)*(4.0*x.powi(2)-2.0)*dd,
+            norm*k.powi(2)*((4.0*x.powi(2)-2.0)*ff-4.0*x/PI.sqrt()),
+        ),
+    }
+}
+
+// Dawson integral (Rybicki's method)
+pub fn dawson(x: f64) -> f64 {
+    let h = 0.4;
+    let xx = x.abs();
+
+    if xx < 0.2 {
+        let x2 = x.powi(2);
+        return x*(1.0-2.0/3.0*x2*(1.0-0.4*x2*(1.0-2.0/7.0*x2)))
+    }
+
+    let n0 = 2.0*(0.5*xx/h+0.5).floor();
+    let xp = xx-n0*h;
+    let mut e1 = (2.0*xp*h).exp();
+    let e2 = e1.powi(2);
+    let mut d1 = n0+1.0;
+    let mut d2 = d1-2.0;
+    let mut sum = 0.0;
+
+    for i in 0..6 {
+        let c = (-((2.0*i as f64+1.0)*h).powi(2)).exp();
+        sum += c*(e1/d1+1.0/(d2*e1));
+        d1 += 2.0;
+        d2 -= 2.0;
+        e1 *= e2;
+    }
+
+    x.signum()/PI.sqrt()*(-xp.powi(2)).exp()*sum
+}
+
+// Lorentzian/Gaussian mix; lrtz is the Lorentzian percentage
+pub fn lineshape(a: f64, lw: f64, lrtz: f64, deriv: usize) -> (f64, f64) {
+    let (mut abs, mut disp) = (0.0, 0.0);
+    if lrtz > 0.0 {
+        let (l_abs, l_disp) = lorentzian(a, lw, deriv);
+        abs += 0.01*lrtz*l_abs;
+        disp += 0.01*lrtz*l_disp;
+    }
+    if lrtz < 100.0 {
+        let (g_abs, g_disp) = gaussian(a, lw, deriv);
+        abs += 0.01*(100.0-lrtz)*g_abs;
+        disp += 0.01*(100.0-lrtz)*g_disp;
+    }
+    (abs, disp)
+}
+
+// Faddeeva function w(z) = exp(-z^2) erfc(-iz) for Im(z) >= 0 (Weideman, N = 32)
+const WEIDEMAN_N: usize = 32;
+thread_local! {
+    static WEIDEMAN_COEFFS: [f64; WEIDEMAN_N] = weideman_coeffs();
+}
+
+fn weideman_coeffs() -> [f64; WEIDEMAN_N] {
+    let m = 2*WEIDEMAN_N;
+    let l = (WEIDEMAN_N as f64/2.0_f64.sqrt()).sqrt();
+    let mut coeffs = [0.0; WEIDEMAN_N];
+
+    for (n, coeff) in coeffs.iter_mut().enumerate() {
+        let mut sum = 0.0;
+        for k in -(m as isize)+1..m as isize {
+            let t = l*(k as f64*PI/(2.0*m as f64)).tan();
+            let f = (-t.powi(2)).exp()*(l.powi(2)+t.powi(2));
+            sum += f*(PI*(k*(n as isize+1)) as f64/m as f64).cos();
+        }
+        *coeff = sum/(2.0*m as f64);
+    }
+
+    coeffs
+}
+
+pub fn faddeeva(z: Complex64) -> Complex64 {
+    let coeffs = WEIDEMAN_COEFFS.with(|coeffs| *coeffs);
+
+    let l = (WEIDEMAN_N as f64/2.0_f64.sqrt()).sqrt();
+    let iz = Complex64::i()*z;
+    let zz = (l+iz)/(l-iz);
+
+    // Horner's scheme, highest degree first
+    let mut p = Complex64::new(0.0, 0.0);
+    for coeff in coeffs.iter().rev() { p = p*zz+coeff; }
+
+    2.0*p/(l-iz).powi(2) + (1.0/PI.sqrt())/(l-iz)
+}
+
+// Voigt profile: convolution of a Gaussian (lwg) and a Lorentzian (lwl), both peak-to-peak widths
+pub fn voigt(a: f64, lwg: f64, lwl: f64, deriv: usize) -> (f64, f64) {
+    // A Gaussian this narrow changes the line by less than 1E-4, while the derivatives of w(z)
+    // lose their precision to cancellation at large z
+    if lwg <= 1E-2*lwl { return lorentzian(a, lwl, deriv) }
+    if lwl <= 0.0 { return gaussian(a, lwg, deriv) }
+
+    let sigma = lwg/2.0;
+    let gamma = 3.0_f64.sqrt()/2.0*lwl;  // HWHM
+    let k = 1.0/(sigma*2.0_f64.sqrt());  // dz/da
+    let norm = 1.0/(sigma*(2.0*PI).sqrt());
+    let z = Complex64::new(a*k, gamma*k);
+    let w = faddeeva(z);
+
+    // Derivatives of w(z): w' = -2zw + 2i/sqrt(pi), w'' = (4z^2-2)w - 4iz/sqrt(pi)
+    let dw = match deriv {
+        0 => w,
+        1 => k*(-2.0*z*w + 2.0*Complex64::i()/PI.sqrt()),
+        _ => k.powi(2)*((4.0*z*z-2.0)*w - 4.0*Complex64::i()*z/PI.sqrt()),
+    };
+
+    (norm*dw.re, norm*dw.im)
+}
+
+// Lock-in response of a line (x, deriv) -> (abs, disp) at the given harmonic of the field modulation.
+// lw is its overall width and mod_amp the peak-to-peak modulation amplitude; when it's zero the idealized derivative is returned.
+// The n-th Fourier coefficient of the modulated line is integrated over a whole period,
+// then scaled so that it converges to the n-th derivative for small amplitudes.
+pub fn harmonic<F>(a: f64, lw: f64, harm: usize, mod_amp: f64, line: F) -> (f64, f64)
+    where F: Fn(f64, usize) -> (f64, f64) {
+    if mod_amp <= 0.0 || lw <= 0.0 { return line(a, harm) }
+
+    let h = mod_amp/2.0;  // Modulation semi-amplitude
+    let steps = (32 + 16*(h/lw).ceil() as usize).min(4096);  // Resolve the line along the cycle
+    let dtheta = 2.0*PI/steps as f64;
+
+    let (mut abs, mut disp) = (0.0, 0.0);
+    for k in 0..steps {
+        let theta = k as f64*dtheta;
+        let (l_abs, l_disp) = line(a+h*theta.cos(), 0);
+        let c = (harm as f64*theta).cos();
+        abs += l_abs*c;
+        disp += l_disp*c;
+    }
+
+    if harm == 0 { return (abs/steps as f64, disp/steps as f64) }
+
+    // 2^n n! / h^n
+    let mut scale = 1.0;
+    for n in 1..=harm { scale *= 2.0*n as f64/h; }
+    (scale*abs/steps as f64, scale*disp/steps as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Area of the absorption over -range..range
+    fn area<F: Fn(f64) -> f64>(line: F, range: f64, step: f64) -> f64 {
+        let n = (2.0*range/step) as usize;
+        (0..=n).map(|k| line(-range+k as f64*step)).sum::<f64>()*step
+    }
+
+    #[test]
+    fn weak_modulation_gives_the_derivatives() {
+        for harm in 1..3 {
+            for &a in [-1.2, -0.3, 0.5, 2.0].iter() {
+                let modulated = harmonic(a, 1.0, harm, 0.01, |x, deriv| lorentzian(x, 1.0, deriv)).0;
+                let ideal = lorentzian(a, 1.0, harm).0;
+                assert!((modulated-ideal).abs() < 1E-3*ideal.abs().max(0.1), "harm {} a {}: {} {}", harm, a, modulated, ideal);
+            }
+        }
+    }
+
+    #[test]
+    fn modulation_keeps_the_area() {
+        // The zeroth harmonic averages the line over the cycle
+        let total = area(|a| harmonic(a, 1.0, 0, 3.0, |x, deriv| gaussian(x, 1.0, deriv)).0, 20.0, 0.01);
+        assert!((total-1.0).abs() < 1E-6, "{}", total);
+    }
+
+    #[test]
+    fn faddeeva_known_values() {
+        let w0 = faddeeva(Complex64::new(0.0, 0.0));
+        assert!((w0.re-1.0).abs() < 1E-10 && w0.im.abs() < 1E-10);
+        let w = faddeeva(Complex64::new(1.0, 1.0));
+        assert!((w.re-0.3047442052569126).abs() < 1E-9, "{}", w);
+        assert!((w.im-0.20821893820283163).abs() < 1E-9, "{}", w);
+    }
+
+    #[test]
+    fn voigt_limits() {
+        for &a in [-3.0, -0.7, 0.0, 0.4, 2.5].iter() {
+            for deriv in 0..3 {
+                let (v, g) = (voigt(a, 1.5, 1E-7, deriv).0, gaussian(a, 1.5, deriv).0);
+                assert!((v-g).abs() < 1E-6, "a {} deriv {}: {} {}", a, deriv, v, g);
+                let (v, l) = (voigt(a, 1E-4, 1.5, deriv).0, lorentzian(a, 1.5, deriv).0);
+                assert!((v-l).abs() < 1E-3*l.abs().max(1E-3), "a {} deriv {}: {} {}", a, deriv, v, l);
+            }
+        }
+    }
+
+    #[test]
+    fn voigt_unit_area() {
+        let total = area(|a| voigt(a, 1.0, 0.5, 0).0, 2000.0, 0.01);
+        assert!((total-1.0).abs() < 1E-3, "{}", total);
+    }
+
+    #[test]
+    fn mode_mix() {
+        let line = (0.7, -0.3);
+        for mode in [Mode::Absorption, Mode::FirstDerivative, Mode::SecondDerivative].iter() {
+            assert_eq!(mode.mix(line, 0.0), 0.7);
+            assert!((mode.mix(line, 90.0)+0.3).abs() < 1E-12);
+        }
+        // Dispersion is the quadrature channel: a -90 phase error brings back the absorption
+        assert!((Mode::Dispersion.mix(line, 0.0)+0.3).abs() < 1E-12);
+        assert!((Mode::Dispersion.mix(line, -90.0)-0.7).abs() < 1E-12);
+        assert!((Mode::Absorption.mix(line, 45.0)-0.4/2.0_f64.sqrt()).abs() < 1E-12);
+    }
+
+    #[test]
+    fn mode_harmonics() {
+        let modes = [Mode::Absorption, Mode::FirstDerivative, Mode::SecondDerivative, Mode::Dispersion];
+        let harmonics: Vec<usize> = modes.iter().map(|mode| mode.harmonic()).collect();
+        assert_eq!(harmonics, [0, 1, 2, 0]);
+
+        // Each mode picks its derivative and channel of the line
+        for mode in modes.iter() {
+            for &a in [-1.3, 0.0, 0.6].iter() {
+                let out = mode.mix(harmonic(a, 1.0, mode.harmonic(), 0.0, |x, deriv| lorentzian(x, 1.0, deriv)), 0.0);
+                let (abs, disp) = lorentzian(a, 1.0, mode.harmonic());
+                let expected = if *mode == Mode::Dispersion { disp } else { abs };
+                assert!((out-expected).abs() < 1E-12, "{:?} {}", mode, a);
+            }
+        }
+        // The dispersion line is odd around the center, the absorption even
+        let disp = |a: f64| Mode::Dispersion.mix(lorentzian(a, 1.0, 0), 0.0);
+        assert!(disp(0.0).abs() < 1E-12 && (disp(0.8)+disp(-0.8)).abs() < 1E-12 && disp(0.8) > 0.0);
+    }
+
+    #[test]
+    fn peak_to_peak_widths() {
+        // The first derivative peaks where the second one vanishes: at +-lw/2
+        assert!(lorentzian(1.0, 2.0, 2).0.abs() < 1E-12);
+        assert!(gaussian(1.0, 2.0, 2).0.abs() < 1E-12);
+    }
+}
@@ -11,9 +11,26 @@ use std::env::args;
 use std::thread;
 
 mod io;
+mod bkg;
 mod plt;
+mod prj;
+mod qcm;
+mod pol;
+mod rsm;
+mod eig;
 mod ent;
+mod esp;
+mod exc;
+mod fft;
+mod flt;
+mod ham;
+mod iso;
+mod jdx;
+mod lsh;
+mod out;
+mod sat;
 mod sim;
+mod stk;
 mod ui;
 
 use crate::ui::ui::{Gui};
@@ -54,6 +71,15 @@ fn main_app(app: &gtk::Application) {
     // Add open action to menu
     let open_action = gui.open_action();
     app.add_action(&open_action);
+
+    // Export actions
+    for action in gui.export_actions() { app.add_action(&action); }
+
+    // Project actions
+    for action in gui.project_actions() { app.add_action(&action); }
+
+    // Simulation actions
+    for action in gui.simulation_actions() { app.add_action(&action); }
 }
 
 fn main() {
@@ -0,0 +1,217 @@
+// Export of the spectra as text columns: field, experimental, simulated, residual, radicals
+use crate::rsm;
+use crate::sim::{Simulator};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Tsv,
+    Ascii,  // Index, field, simulated: what io::read_ascii reads back; exp, residual and radicals are left out
+}
+
+// Field axis of the exported points
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grid {
+    Simulation,  // exp resampled onto the simulation grid
+    Experimental,  // Simulated columns resampled onto the experimental axis
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalization {
+    None,
+    Fit,  // Simulated columns scaled to exp by least squares
+    Unit,  // Simulated and exp scaled to a maximum of 1 each
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Ascii => "txt",
+        }
+    }
+}
+
+// Named columns, the field first; exp and residual only with an experimental spectrum
+pub fn table(sim: &Simulator, grid: Grid, norm: Normalization) -> Vec<(String, Vec<f64>)> {
+    let rads = sim.rads.lock().unwrap().clone();
+    let axis = sim.field_axis();
+    let teor = sim.calcola(rads.clone());
+    let parts = sim.parts(&rads);
+    let exp = sim.exp.lock().unwrap().clone();
+    let has_exp = !exp.is_empty();
+
+    // Background and baseline: what the radicals don't account for
+    let background = sim.background();
+    let mut simulated = vec![("Simulated".to_string(), teor)];
+    simulated.extend(parts.into_iter().enumerate().map(|(r, part)| (format!("Radical {}", r), part)));
+    if background.iter().any(|b| *b != 0.0) { simulated.push(("Background".to_string(), background)); }
+
+    // Points
+    let (fld, mut exp_int) = match grid {
+        Grid::Experimental if has_exp => {
+            let resampling = *sim.resampling.lock().unwrap();
+            for (_, col) in simulated.iter_mut() { *col = rsm::resample(&axis, col, &exp.fld, resampling); }
+            (exp.fld.clone(), exp.int.clone())
+        },
+        _ => (axis, sim.exp_on_grid()),
+    };
+
+    // Scale of the simulated columns, and of exp
+    let (scale, exp_scale) = match norm {
+        Normalization::None => (1.0, 1.0),
+        Normalization::Fit => {
+            let teor = &simulated[0].1;
+            let sim_sq: f64 = teor.iter().map(|t| t*t).sum();
+            let cross: f64 = teor.iter().zip(&exp_int).map(|(t, e)| t*e).sum();
+            (if has_exp && sim_sq > 0.0 { cross/sim_sq } else { 1.0 }, 1.0)
+        },
+        Normalization::Unit => {
+            let max = |col: &[f64]| col.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+            let (sim_max, exp_max) = (max(&simulated[0].1), max(&exp_int));
+            (if sim_max > 0.0 { 1.0/sim_max } else { 1.0 }, if exp_max > 0.0 { 1.0/exp_max } else { 1.0 })
+        },
+    };
+    for (_, col) in simulated.iter_mut() { for y in col.iter_mut() { *y *= scale; } }
+    for y in exp_int.iter_mut() { *y *= exp_scale; }
+
+    let mut columns = vec![("Field [G]".to_string(), fld)];
+    if has_exp {
+        let residual = exp_int.iter().zip(&simulated[0].1).map(|(e, t)| e-t).collect();
+        columns.push(("Experimental".to_string(), exp_int));
+        columns.push((simulated[0].0.clone(), simulated.remove(0).1));
+        columns.push(("Residual".to_string(), residual));
+    }
+    columns.extend(simulated);
+    columns
+}
+
+pub fn write(columns: &[(String, Vec<f64>)], format: Format) -> String {
+    let rows = columns.iter().map(|(_, col)| col.len()).min().unwrap_or(0);
+    let delimiter = if format == Format::Csv { "," } else { "\t" };
+
+    // The three-column text holds the field and the total simulated spectrum
+    let columns: Vec<&(String, Vec<f64>)> = match format {
+        Format::Ascii => columns.iter().filter(|(name, _)| name.starts_with("Field") || name == "Simulated").collect(),
+        _ => columns.iter().collect(),
+    };
+
+    let mut out = match format {
+        Format::Ascii => "Index\tField [G]\tIntensity\n".to_string(),
+        _ => columns.iter().map(|(name, _)| quote(name, delimiter)).collect::<Vec<String>>().join(delimiter)+"\n",
+    };
+    for j in 0..rows {
+        let row: Vec<String> = columns.iter().map(|(_, col)| col[j].to_string()).collect();
+        if format == Format::Ascii { out += &format!("{}\t", j+1); }
+        out += &row.join(delimiter);
+        out += "\n";
+    }
+    out
+}
+
+pub fn export(sim: &Simulator, format: Format, grid: Grid, norm: Normalization) -> String {
+    write(&table(sim, grid, norm), format)
+}
+
+// Names holding the delimiter or quotes are quoted
+fn quote(name: &str, delimiter: &str) -> String {
+    if name.contains(delimiter) || name.contains('"') { format!("\"{}\"", name.replace('"', "\"\"")) } else { name.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ent::{Radical};
+    use crate::io::{Spectrum};
+
+    // Two radicals; exp is three times their sum on an axis of its own, inside the sweep
+    fn simulator() -> Simulator {
+        let sim = Simulator::new();
+        sim.set_points(128);
+        *sim.rads.lock().unwrap() = vec![Radical::electron(), Radical::probe()];
+        let rads = sim.rads.lock().unwrap().clone();
+        let teor = sim.calcola(rads);
+        let axis = sim.field_axis();
+        let fld: Vec<f64> = (0..100).map(|j| axis[10]+j as f64*(axis[110]-axis[10])/99.0).collect();
+        let int = rsm::resample(&axis, &teor, &fld, rsm::Resampling::Spline).iter().map(|y| 3.0*y).collect();
+        let exp = Spectrum::new(fld, int);
+        *sim.exp.lock().unwrap() = exp;
+        sim
+    }
+
+    fn names(columns: &[(String, Vec<f64>)]) -> Vec<&str> {
+        columns.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn columns_and_headers() {
+        let sim = simulator();
+        let columns = table(&sim, Grid::Simulation, Normalization::None);
+        assert_eq!(names(&columns), ["Field [G]", "Experimental", "Simulated", "Residual", "Radical 0", "Radical 1"]);
+
+        let csv = write(&columns, Format::Csv);
+        assert_eq!(csv.lines().next(), Some("Field [G],Experimental,Simulated,Residual,Radical 0,Radical 1"));
+        assert_eq!(csv.lines().count(), 129);
+        assert!(csv.lines().skip(1).all(|line| line.split(',').count() == 6));
+
+        let tsv = write(&columns, Format::Tsv);
+        assert_eq!(tsv.lines().next(), Some("Field [G]\tExperimental\tSimulated\tResidual\tRadical 0\tRadical 1"));
+
+        // Index, field and the total simulated spectrum only
+        let ascii = write(&columns, Format::Ascii);
+        assert_eq!(ascii.lines().next(), Some("Index\tField [G]\tIntensity"));
+        let row: Vec<&str> = ascii.lines().nth(1).unwrap().split('\t').collect();
+        assert_eq!(row, ["1", &columns[0].1[0].to_string(), &columns[2].1[0].to_string()]);
+
+        // Without exp: the field and the simulated columns
+        *sim.exp.lock().unwrap() = Spectrum::default();
+        assert_eq!(names(&table(&sim, Grid::Experimental, Normalization::Fit)), ["Field [G]", "Simulated", "Radical 0", "Radical 1"]);
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("Field [G]", ","), "Field [G]");
+        assert_eq!(quote("a,b", ","), "\"a,b\"");
+        assert_eq!(quote("a,b", "\t"), "a,b");
+        assert_eq!(quote("say \"hi\"", "\t"), "\"say \"\"hi\"\"\"");
+        let columns = vec![("Field [G]".to_string(), vec![1.0]), ("x, y".to_string(), vec![2.5])];
+        assert_eq!(write(&columns, Format::Csv), "Field [G],\"x, y\"\n1,2.5\n");
+    }
+
+    #[test]
+    fn grids() {
+        let sim = simulator();
+        let exp = sim.exp.lock().unwrap().clone();
+        let on_exp = table(&sim, Grid::Experimental, Normalization::None);
+        assert_eq!(on_exp[0].1, exp.fld);
+        assert_eq!(on_exp[1].1, exp.int);
+        assert!(on_exp.iter().all(|(_, col)| col.len() == 100));
+
+        let on_sim = table(&sim, Grid::Simulation, Normalization::None);
+        assert_eq!(on_sim[0].1, sim.field_axis());
+        assert_eq!(on_sim[1].1, sim.exp_on_grid());
+        let rads = sim.rads.lock().unwrap().clone();
+        assert_eq!(on_sim[2].1, sim.calcola(rads));
+    }
+
+    #[test]
+    fn normalization_and_residual() {
+        let sim = simulator();
+        for norm in [Normalization::None, Normalization::Fit, Normalization::Unit].iter() {
+            let columns = table(&sim, Grid::Experimental, *norm);
+            for j in 0..100 { assert_eq!(columns[3].1[j], columns[1].1[j]-columns[2].1[j]); }
+        }
+
+        // exp is three times the simulation: the fit scales it up, the residual vanishes
+        let fit = table(&sim, Grid::Experimental, Normalization::Fit);
+        let max = |col: &[f64]| col.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+        assert!(max(&fit[3].1) < 1E-6*max(&fit[1].1));
+        let none = table(&sim, Grid::Experimental, Normalization::None);
+        assert!((fit[4].1[50]-3.0*none[4].1[50]).abs() < 1E-6*max(&none[4].1));
+
+        let unit = table(&sim, Grid::Experimental, Normalization::Unit);
+        assert_eq!(max(&unit[1].1), 1.0);
+        assert!((max(&unit[2].1)-1.0).abs() < 1E-12);
+    }
+}
@@ -0,0 +1,81 @@
+// Chemically induced dynamic electron polarization (CIDEP) of the stick spectra.
+// Lines are weighted by 1 + net + mult*m, where m is the radical pair multiplet pattern:
+// the average over the partner lines of sgn(Q) sqrt(|Q|), Q = (B_k - B_j)/2 being the S-T0 mixing
+// between the line and the partner line (radical pair mechanism, diffusing pairs).
+// Positive mult gives the E/A pattern: emissive at low field, absorptive at high field.
+use crate::stk::{Stick};
+
+// Multiplet pattern of the lines at positions pos (absolute, G) against the partner sticks,
+// normalized to a maximum of 1. Without a partner the lines pair with the center (0.0).
+pub fn multiplet(pos: &[f64], partner: &[Stick]) -> Vec<f64> {
+    let own = [Stick { pos: 0.0, int: 1.0 }];
+    let partner = if partner.is_empty() { &own[..] } else { partner };
+    let total: f64 = partner.iter().map(|s| s.int).sum();
+
+    let mult: Vec<f64> = pos.iter().map(|b| {
+        partner.iter().map(|s| {
+            let q = (b-s.pos)/2.0;
+            s.int*q.signum()*q.abs().sqrt()
+        }).sum::<f64>()/total
+    }).collect();
+
+    let max = mult.iter().fold(0.0_f64, |m, x| m.max(x.abs()));
+    if max > 0.0 { mult.iter().map(|m| m/max).collect() } else { mult }
+}
+
+// Polarized sticks; dh1 places the radical's sticks on the absolute axis of the partner
+pub fn polarize(sticks: &[Stick], dh1: f64, net: f64, mult: f64, partner: &[Stick]) -> Vec<Stick> {
+    if net == 0.0 && mult == 0.0 { return sticks.to_vec() }
+
+    let shift = if partner.is_empty() { 0.0 } else { dh1 };
+    let pos: Vec<f64> = sticks.iter().map(|s| s.pos+shift).collect();
+    let pattern = multiplet(&pos, partner);
+    sticks.iter().zip(pattern).map(|(s, m)| Stick { pos: s.pos, int: s.int*(1.0+net+mult*m) }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triplet() -> Vec<Stick> {
+        vec![Stick { pos: -15.0, int: 1.0 }, Stick { pos: 0.0, int: 1.0 }, Stick { pos: 15.0, int: 1.0 }]
+    }
+
+    #[test]
+    fn boltzmann_without_polarization() {
+        assert_eq!(polarize(&triplet(), 3.0, 0.0, 0.0, &triplet()), triplet());
+    }
+
+    #[test]
+    fn positive_multiplet_is_emissive_at_low_field() {
+        let polarized = polarize(&triplet(), 0.0, 0.0, 4.0, &[]);
+        assert!(polarized[0].int < 0.0);
+        assert_eq!(polarized[1].int, 1.0);  // The center line doesn't mix
+        assert!(polarized[2].int > 1.0);
+        assert!((polarized[0].int+polarized[2].int-2.0).abs() < 1E-12);  // Antisymmetric around the center
+        assert!(polarize(&triplet(), 0.0, 0.0, -4.0, &[])[0].int > 1.0);
+    }
+
+    #[test]
+    fn net_polarization_scales_every_line() {
+        let emissive = polarize(&triplet(), 0.0, -3.0, 0.0, &[]);
+        assert!(emissive.iter().zip(triplet()).all(|(p, s)| p.pos == s.pos && p.int == -2.0*s.int));
+        let enhanced = polarize(&triplet(), 0.0, 5.0, 0.0, &[]);
+        assert!(enhanced.iter().all(|p| p.int == 6.0));
+    }
+
+    #[test]
+    fn multiplet_is_normalized() {
+        let pos = [-20.0, -5.0, 1.0, 12.0];
+        let pattern = multiplet(&pos, &[]);
+        assert_eq!(pattern.iter().fold(0.0_f64, |m, x| m.max(x.abs())), 1.0);
+        assert_eq!(pattern[0], -1.0);
+        assert!((pattern[3]-(6.0_f64/10.0).sqrt()).abs() < 1E-12);
+        assert_eq!(multiplet(&[0.0], &[]), vec![0.0]);
+
+        // dh1 moves the lines against the partner: a radical above it is all absorptive with E/A
+        let partner = [Stick { pos: 0.0, int: 2.0 }];
+        let shifted = polarize(&[Stick { pos: -1.0, int: 1.0 }, Stick { pos: 1.0, int: 1.0 }], 10.0, 0.0, 1.0, &partner);
+        assert!(shifted[0].int > 1.0 && shifted[1].int > shifted[0].int);
+    }
+}
@@ -0,0 +1,263 @@
+// Project files: experiment, simulator settings, radicals and fit, as versioned JSON
+use crate::bkg::{Baseline};
+use crate::ent::{Radical, Param};
+use crate::flt::{Direction};
+use crate::io;
+use crate::io::{ImportError, Spectrum};
+use crate::lsh::{Mode};
+use crate::rsm::{Resampling};
+use crate::sim;
+use crate::sim::{Simulator};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Written into every file; older files are migrated on load
+pub const VERSION: u64 = 1;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Project {
+    pub version: u64,
+    pub exp: Experiment,
+    pub settings: Settings,
+    pub rads: Vec<Radical>,  // Fit settings too: a Param varies when var > 0.0
+    pub fit: Fit,
+}
+
+// The experimental spectrum, embedded or by reference
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Experiment {
+    pub source: Option<PathBuf>,  // File it was read from
+    pub data: Option<Spectrum>,  // Embedded data; without it the source is read again
+}
+
+// Simulator settings
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub points: usize,
+    pub sweep: f64,
+    pub center: f64,
+    pub resampling: Resampling,
+    pub freq: f64,
+    pub mod_amp: f64,
+    pub mode: Mode,
+    pub phase: Param,
+    pub b1: f64,
+    pub time_const: Param,
+    pub conv_time: f64,
+    pub direction: Direction,
+    pub baseline: Baseline,
+}
+
+// Best fit and its statistics
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Fit {
+    pub iters: usize,  // MC iterations so far
+    pub sigma: f64,  // Deviation of the best fit
+    pub teor: Vec<f64>,  // Best simulated spectrum, on the settings grid
+}
+
+impl Default for Project {
+    fn default() -> Self {
+        Project::from_simulator(&Simulator::new(), true)
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings::from_simulator(&Simulator::new())
+    }
+}
+
+impl Default for Fit {
+    fn default() -> Self {
+        Fit { iters: 0, sigma: sim::UNSCORED, teor: Vec::new() }
+    }
+}
+
+impl Settings {
+    pub fn from_simulator(sim: &Simulator) -> Settings {
+        Settings {
+            points: sim.points(),
+            sweep: *sim.sweep.lock().unwrap(),
+            center: *sim.center.lock().unwrap(),
+            resampling: *sim.resampling.lock().unwrap(),
+            freq: *sim.freq.lock().unwrap(),
+            mod_amp: *sim.mod_amp.lock().unwrap(),
+            mode: *sim.mode.lock().unwrap(),
+            phase: sim.phase.lock().unwrap().clone(),
+            b1: *sim.b1.lock().unwrap(),
+            time_const: sim.time_const.lock().unwrap().clone(),
+            conv_time: *sim.conv_time.lock().unwrap(),
+            direction: *sim.direction.lock().unwrap(),
+            baseline: sim.baseline.lock().unwrap().clone(),
+        }
+    }
+
+    pub fn apply(&self, sim: &Simulator) {
+        sim.set_points(self.points);
+        *sim.sweep.lock().unwrap() = self.sweep;
+        *sim.center.lock().unwrap() = self.center;
+        *sim.resampling.lock().unwrap() = self.resampling;
+        *sim.freq.lock().unwrap() = self.freq;
+        *sim.mod_amp.lock().unwrap() = self.mod_amp;
+        *sim.mode.lock().unwrap() = self.mode;
+        *sim.phase.lock().unwrap() = self.phase.clone();
+        *sim.b1.lock().unwrap() = self.b1;
+        *sim.time_const.lock().unwrap() = self.time_const.clone();
+        *sim.conv_time.lock().unwrap() = self.conv_time;
+        *sim.direction.lock().unwrap() = self.direction;
+        *sim.baseline.lock().unwrap() = self.baseline.clone();
+    }
+}
+
+impl Project {
+    // Snapshot of the simulator; embed keeps the experimental data in the file
+    pub fn from_simulator(sim: &Simulator, embed: bool) -> Project {
+        let exp = sim.exp.lock().unwrap().clone();
+        Project {
+            version: VERSION,
+            exp: Experiment {
+                source: exp.source.clone(),
+                data: if embed && !exp.is_empty() { Some(exp) } else { None },
+            },
+            settings: Settings::from_simulator(sim),
+            rads: sim.rads.lock().unwrap().clone(),
+            fit: Fit {
+                iters: *sim.iters.lock().unwrap(),
+                sigma: *sim.sigma.lock().unwrap(),
+                teor: sim.teor.lock().unwrap().clone(),
+            },
+        }
+    }
+
+    // Restore the simulator. The experiment goes first: its axis is then overridden by the settings
+    pub fn apply(self, sim: &Simulator) -> Result<(), ProjectError> {
+        let exp = match (self.exp.data, &self.exp.source) {
+            (Some(data), _) => data,
+            (None, Some(source)) => io::open(source).map_err(|error| ProjectError::Data(source.clone(), error))?,
+            (None, None) => Spectrum::default(),
+        };
+        sim.set_exp(exp);
+        self.settings.apply(sim);
+        *sim.rads.lock().unwrap() = self.rads;
+        *sim.teor.lock().unwrap() = self.fit.teor;
+        *sim.iters.lock().unwrap() = self.fit.iters;
+        // Scored again: the saved sigma may refer to a source file changed since
+        sim.score();
+        Ok(())
+    }
+}
+
+// Load failure
+#[derive(Debug)]
+pub enum ProjectError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    Version(u64),  // Written by a newer release
+    Data(PathBuf, ImportError),  // The referenced experimental spectrum
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProjectError::Io(error) => write!(f, "{}", error),
+            ProjectError::Format(error) => write!(f, "Not a project file: {}", error),
+            ProjectError::Version(version) => write!(f, "Project version {} is newer than this release ({})", version, VERSION),
+            ProjectError::Data(path, error) => write!(f, "Couldn't read the experiment {}\n{}", path.display(), error),
+        }
+    }
+}
+
+impl From<std::io::Error> for ProjectError {
+    fn from(error: std::io::Error) -> Self { ProjectError::Io(error) }
+}
+
+impl From<serde_json::Error> for ProjectError {
+    fn from(error: serde_json::Error) -> Self { ProjectError::Format(error) }
+}
+
+pub fn save(sim: &Simulator, path: &Path) -> std::io::Result<()> {
+    let project = Project::from_simulator(sim, true);
+    fs::write(path, serde_json::to_string_pretty(&project)?)
+}
+
+pub fn load(path: &Path) -> Result<Project, ProjectError> {
+    let value: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    Ok(serde_json::from_value(migrate(value)?)?)
+}
+
+// Bring a file up to VERSION, one version at a time
+fn migrate(mut value: Value) -> Result<Value, ProjectError> {
+    loop {
+        let version = value.get("version").and_then(|version| version.as_u64()).unwrap_or(0);
+        if version == VERSION { return Ok(value) }
+        if version > VERSION { return Err(ProjectError::Version(version)) }
+
+        value = match version {
+            // Unversioned: a bare list of radicals, or a project without its version
+            0 => match value {
+                Value::Array(rads) => json!({ "version": 1, "rads": rads }),
+                Value::Object(mut project) => { project.insert("version".to_string(), json!(1)); Value::Object(project) },
+                other => return Err(ProjectError::Format(serde::de::Error::custom(format!("unexpected {}", other)))),
+            },
+            _ => unreachable!(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bkg::{BroadShape};
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gfactor-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn round_trip() {
+        let sim = Simulator::new();
+        let fld: Vec<f64> = (0..64).map(|i| 3350.0+i as f64).collect();
+        let mut exp = Spectrum::new(fld, (0..64).map(|i| (i as f64*0.3).sin()).collect());
+        exp.acq.freq = Some(9.41);
+        sim.set_exp(exp);
+        *sim.rads.lock().unwrap() = vec![Radical::probe(), Radical::electron()];
+        *sim.phase.lock().unwrap() = Param::set(3.0, 1.0);
+        *sim.baseline.lock().unwrap() = Baseline { poly: vec![Param::set(0.1, 0.0); 2], broad: None }
+            .with_broad(BroadShape::Gaussian, 2.0, 5.0, 30.0);
+        *sim.iters.lock().unwrap() = 160;
+        sim.score();
+
+        let path = scratch("project").join("round.gfp");
+        save(&sim, &path).unwrap();
+        let back = Simulator::new();
+        load(&path).unwrap().apply(&back).unwrap();
+
+        let json = |sim: &Simulator| serde_json::to_value(Project::from_simulator(sim, true)).unwrap();
+        assert_eq!(json(&back), json(&sim));
+        assert_eq!(*back.iters.lock().unwrap(), 160);
+        assert_eq!(back.exp.lock().unwrap().acq.freq, Some(9.41));
+    }
+
+    #[test]
+    fn unversioned_list_of_radicals() {
+        let path = scratch("project").join("v0.json");
+        fs::write(&path, serde_json::to_string(&vec![Radical::probe()]).unwrap()).unwrap();
+        let project = load(&path).unwrap();
+        assert_eq!(project.version, VERSION);
+        assert_eq!(project.rads.len(), 1);
+        assert_eq!(project.rads[0].nucs[0].hpf.val, 14.0);
+
+        fs::write(&path, "{\"version\": 99}").unwrap();
+        assert!(matches!(load(&path), Err(ProjectError::Version(99))));
+    }
+}
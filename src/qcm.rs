@@ -0,0 +1,257 @@
+// Hyperfine couplings and g from quantum chemistry outputs (ORCA, Gaussian)
+use crate::ent::{Nucleus, Param, Radical};
+use crate::ham;
+use crate::ham::{BMAGN, GE};
+use crate::io::{ImportError};
+use crate::iso;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Program {
+    Orca,
+    Gaussian,
+}
+
+// Isotropic coupling of an atom
+#[derive(Clone, Debug)]
+pub struct Coupling {
+    pub atom: usize,  // Index in the output
+    pub isotope: String,  // e.g. "14N"
+    pub aiso: f64,  // (G)
+}
+
+#[derive(Clone, Debug)]
+pub struct Prediction {
+    pub program: Program,
+    pub g: Option<f64>,  // Isotropic g of the molecule
+    pub couplings: Vec<Coupling>,
+}
+
+// Output of either program, told apart by the ORCA banner
+pub fn read(content: &str) -> Result<Prediction, ImportError> {
+    if content.contains("O   R   C   A") { read_orca(content) } else { read_gaussian(content).or_else(|_| read_orca(content)) }
+}
+
+// ORCA EPR/NMR section: "Nucleus   3H : I=  0.5 ..." then "A(iso)= ..." (or A:ISO=) in MHz; g from "g(tot)"
+pub fn read_orca(content: &str) -> Result<Prediction, ImportError> {
+    let mut g = None;
+    let mut mhz: Vec<(usize, String, f64)> = Vec::new();  // Atom, isotope, Aiso (MHz)
+    let mut current: Option<(usize, String)> = None;
+
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        let number = |text: &str| -> Result<f64, ImportError> {
+            let text = text.split_whitespace().next().unwrap_or("");
+            text.parse().map_err(|_| ImportError::Number { line: n+1, text: text.to_string() })
+        };
+
+        if let Some(rest) = line.strip_prefix("g(tot)") {
+            g = Some(match line.find("iso=") {
+                Some(k) => number(&line[k+4..])?,
+                None => {
+                    let values = rest.split_whitespace().take(3).map(number).collect::<Result<Vec<f64>, _>>()?;
+                    values.iter().sum::<f64>()/values.len().max(1) as f64
+                },
+            });
+        } else if let Some(rest) = line.strip_prefix("Nucleus") {
+            // Atom index and element, e.g. "12H"; the spin picks the isotope
+            let label = rest.split_whitespace().next().unwrap_or("");
+            let digits = label.chars().take_while(|c| c.is_ascii_digit()).count();
+            let (index, element) = (&label[..digits], label[digits..].trim_end_matches(':'));
+            let spin = line.find("I=").map(|k| number(&line[k+2..])).transpose()?;
+            current = match (index.parse::<usize>(), isotope(element, spin)) {
+                (Ok(index), Some(isotope)) => Some((index, isotope)),
+                _ => None,
+            };
+        }
+        // On the following lines, or on the same one
+        if let Some(k) = line.find("A(iso)").or_else(|| line.find("A:ISO")) {
+            let value = &line[k..];
+            if let (Some(eq), Some((atom, isotope))) = (value.find('='), current.take()) {
+                mhz.push((atom, isotope, number(&value[eq+1..])?));
+            }
+        }
+    }
+
+    // MHz to G with the g of the molecule
+    let factor = g.unwrap_or(GE)*BMAGN;
+    let couplings: Vec<Coupling> = mhz.into_iter().map(|(atom, isotope, a)| Coupling { atom, isotope, aiso: a/factor }).collect();
+    if couplings.is_empty() { return Err(ImportError::NoData) }
+    Ok(Prediction { program: Program::Orca, g, couplings })
+}
+
+// Gaussian: the last "Isotropic Fermi Contact Couplings" table (Gauss column); g from the trace of the g tensor
+pub fn read_gaussian(content: &str) -> Result<Prediction, ImportError> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut g = None;
+    let mut couplings = Vec::new();
+
+    for (n, line) in lines.iter().enumerate() {
+        if line.contains("Isotropic Fermi Contact Couplings") {
+            couplings.clear();
+            // Header, then "  1  N(14)   0.0987   31.8798   11.3756   10.6341" rows
+            for (m, row) in lines.iter().enumerate().skip(n+2) {
+                let cols: Vec<&str> = row.split_whitespace().collect();
+                let (atom, label) = match (cols.first().and_then(|c| c.parse::<usize>().ok()), cols.get(1)) {
+                    (Some(atom), Some(label)) if cols.len() >= 5 => (atom, *label),
+                    _ => break,
+                };
+                let isotope = match label.find('(') {
+                    Some(k) => format!("{}{}", label[k+1..].trim_end_matches(')'), &label[..k]),
+                    None => continue,
+                };
+                let aiso = cols[4].parse().map_err(|_| ImportError::Number { line: m+1, text: cols[4].to_string() })?;
+                if iso::get(&isotope).is_some() { couplings.push(Coupling { atom, isotope, aiso }); }
+            }
+        } else if line.contains("g tensor [g =") {
+            // XX= 2.0060 YX= ... on the next three lines; the trace is the isotropic g
+            let tensor: String = lines.iter().skip(n+1).take(3).cloned().collect::<Vec<&str>>().join(" ");
+            let diagonal: Vec<f64> = ["XX=", "YY=", "ZZ="].iter().filter_map(|key| {
+                let k = tensor.find(key)?;
+                tensor[k+3..].split_whitespace().next()?.parse().ok()
+            }).collect();
+            if diagonal.len() == 3 { g = Some(diagonal.iter().sum::<f64>()/3.0); }
+        }
+    }
+
+    if couplings.is_empty() { return Err(ImportError::NoData) }
+    Ok(Prediction { program: Program::Gaussian, g, couplings })
+}
+
+// Isotope of an element with the given spin, else its most abundant magnetic one
+fn isotope(element: &str, spin: Option<f64>) -> Option<String> {
+    let by_spin = spin.and_then(|spin| most_abundant(iso::element(element).into_iter().filter(|iso| (iso.spin-spin).abs() < 1E-6)));
+    by_spin.or_else(|| iso::main_magnetic(element)).map(|iso| iso.symbol.to_string())
+}
+
+fn most_abundant(isotopes: impl Iterator<Item = iso::Isotope>) -> Option<iso::Isotope> {
+    isotopes.fold(None, |best: Option<iso::Isotope>, iso| match best {
+        Some(best) if best.abund >= iso.abund => Some(best),
+        _ => Some(iso),
+    })
+}
+
+// Nuclei of the same isotope whose couplings differ less than tol (G) form one equivalent group,
+// with the mean coupling; couplings within tol of zero are left out.
+// Elements mostly non-magnetic (13C, 17O, 33S) come in their natural abundance.
+pub fn group(couplings: &[Coupling], tol: f64) -> Vec<Nucleus> {
+    let mut sorted: Vec<&Coupling> = couplings.iter()
+        .filter(|c| c.aiso.abs() > tol && iso::get(&c.isotope).is_some_and(|iso| iso.spin > 0.0))
+        .collect();
+    sorted.sort_by(|a, b| a.isotope.cmp(&b.isotope).then(b.aiso.partial_cmp(&a.aiso).unwrap()));
+
+    let mut groups: Vec<(String, Vec<f64>)> = Vec::new();
+    for c in sorted {
+        match groups.last_mut() {
+            Some((isotope, values)) if *isotope == c.isotope && (c.aiso-values[0]).abs() <= tol => values.push(c.aiso),
+            _ => groups.push((c.isotope.clone(), vec![c.aiso])),
+        }
+    }
+
+    groups.into_iter().map(|(symbol, values)| {
+        let hpf = values.iter().sum::<f64>()/values.len() as f64;
+        let eqs = values.len() as f64;
+        let element = iso::get(&symbol).map_or("", |iso| iso.element);
+        match most_abundant(iso::element(element).into_iter()) {
+            Some(main) if main.spin == 0.0 => Nucleus::natural(&symbol, hpf, eqs),
+            _ => Nucleus::isotope(&symbol, hpf, eqs),
+        }
+    }).collect()
+}
+
+impl Prediction {
+    // New radical from the prediction at the microwave frequency; couplings vary by 10% for the fit
+    pub fn radical(&self, freq: f64, tol: f64) -> Radical {
+        let b0 = ham::center_field(freq);
+        let dh1 = b0*(GE/self.g.unwrap_or(GE)-1.0);
+        let mut nucs = group(&self.couplings, tol);
+        for nuc in nucs.iter_mut() { nuc.hpf = Param::set(nuc.hpf.val, 0.1*nuc.hpf.val.abs()); }
+        Radical::set(1.0, 50.0, 100.0, dh1, nucs)
+    }
+
+    // Couplings per atom and the equivalent groups
+    pub fn report(&self, tol: f64) -> String {
+        let mut report = format!("{:?} output, g = {}\n", self.program, self.g.map_or("not found".to_string(), |g| format!("{:.6}", g)));
+        for c in &self.couplings { report += &format!("  atom {:>3} {:>5} {:>10.4} G\n", c.atom, c.isotope, c.aiso); }
+        report += &format!("Equivalent within {} G:\n", tol);
+        for nuc in group(&self.couplings, tol) {
+            report += &format!("  {} x{} {:>10.4} G\n", nuc.iso.unwrap_or_default(), nuc.eqs.val, nuc.hpf.val);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORCA: &str = "\
+                                 * O   R   C   A *
+ ELECTRONIC G-MATRIX
+ g(tot)     2.0023450   2.0034567   2.0045678 iso=  2.0034565
+ Nucleus   0N : I=  1.0 P= 43.2 MHz/au**3
+ A(iso)=   30.0000  A(dip) ...
+ Nucleus   1H : I=  0.5 P= 533.5 MHz/au**3
+ A(iso)=  -60.0000
+ Nucleus   2H : I=  0.5 P= 533.5 MHz/au**3
+ A(iso)=  -60.0500
+ Nucleus   3C : I=  0.5 P= 134.2 MHz/au**3
+ A(iso)=    0.0100
+";
+
+    const GAUSSIAN: &str = "\
+ g tensor [g = g_e + g_RMC + g_DC + g_OZ/SOC]:
+   XX=   2.0031 YX=   0.0000 ZX=   0.0000
+   XY=   0.0000 YY=   2.0041 ZY=   0.0000
+   XZ=   0.0000 YZ=   0.0000 ZZ=   2.0051
+ Isotropic Fermi Contact Couplings
+        Atom                 a.u.       MegaHertz       Gauss      10(-4) cm-1
+     1  N(14)              0.0987      31.8798      11.3756      10.6341
+     2  H(1)              -0.0100     -44.6970     -15.9491     -14.9095
+     3  H(1)              -0.0100     -44.6970     -15.9491     -14.9095
+     4  C(13)              0.0001       0.1000       0.0357       0.0334
+ --------------------------------------------------------
+";
+
+    #[test]
+    fn orca() {
+        let prediction = read(ORCA).unwrap();
+        assert_eq!(prediction.program, Program::Orca);
+        assert!((prediction.g.unwrap()-2.0034565).abs() < 1E-9);
+
+        let isotopes: Vec<&str> = prediction.couplings.iter().map(|c| c.isotope.as_str()).collect();
+        assert_eq!(isotopes, ["14N", "1H", "1H", "13C"]);
+        // MHz to G with the g of the molecule
+        assert!((prediction.couplings[0].aiso-30.0/(2.0034565*BMAGN)).abs() < 1E-9);
+
+        // Both H in one group; the 13C coupling is below the tolerance
+        let nucs = group(&prediction.couplings, 0.1);
+        assert_eq!(nucs.len(), 2);
+        let h = nucs.iter().find(|nuc| nuc.iso.as_deref() == Some("1H")).unwrap();
+        assert_eq!(h.eqs.val, 2.0);
+        assert!((h.hpf.val+60.025/(2.0034565*BMAGN)).abs() < 1E-9);
+    }
+
+    #[test]
+    fn gaussian() {
+        let prediction = read(GAUSSIAN).unwrap();
+        assert_eq!(prediction.program, Program::Gaussian);
+        assert!((prediction.g.unwrap()-2.0041).abs() < 1E-9);
+        assert_eq!(prediction.couplings.len(), 4);
+        assert_eq!(prediction.couplings[0].isotope, "14N");
+        assert_eq!(prediction.couplings[0].aiso, 11.3756);
+
+        let nucs = group(&prediction.couplings, 0.1);
+        assert_eq!(nucs.len(), 2);
+        assert!(nucs.iter().any(|nuc| nuc.iso.as_deref() == Some("1H") && nuc.eqs.val == 2.0 && nuc.hpf.val == -15.9491));
+
+        // The g shift moves the radical off the free electron field
+        let rad = prediction.radical(9.5, 0.1);
+        assert!((rad.dh1.val-ham::center_field(9.5)*(GE/2.0041-1.0)).abs() < 1E-9);
+        assert!(prediction.report(0.1).contains("Equivalent within 0.1 G"));
+    }
+
+    #[test]
+    fn no_couplings() {
+        assert!(read("nothing to see here").is_err());
+    }
+}
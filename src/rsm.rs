@@ -0,0 +1,131 @@
+// Resampling of spectra onto another field axis
+use serde::{Serialize, Deserialize};
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum Resampling {
+    Linear,
+    #[default]
+    Spline,  // Natural cubic spline
+    BandLimited,  // Lanczos windowed sinc, low-passed when the grid is coarser
+}
+
+// Lobes of the Lanczos window
+const LANCZOS: f64 = 8.0;
+
+// y sampled on the ascending axis x, evaluated on grid; zero outside x
+pub fn resample(x: &[f64], y: &[f64], grid: &[f64], method: Resampling) -> Vec<f64> {
+    let n = x.len().min(y.len());
+    if n < 2 { return vec![0.0; grid.len()] }
+    let (x, y) = (&x[..n], &y[..n]);
+
+    match method {
+        Resampling::Linear => grid.iter().map(|&g| match index(x, g) {
+            Some(t) => {
+                let i = (t.floor() as usize).min(n-2);
+                y[i]+(t-i as f64)*(y[i+1]-y[i])
+            },
+            None => 0.0,
+        }).collect(),
+
+        Resampling::Spline => {
+            let m = spline(x, y);
+            grid.iter().map(|&g| match index(x, g) {
+                Some(t) => {
+                    let i = (t.floor() as usize).min(n-2);
+                    let h = x[i+1]-x[i];
+                    let (a, b) = ((x[i+1]-g)/h, (g-x[i])/h);
+                    a*y[i]+b*y[i+1]+((a.powi(3)-a)*m[i]+(b.powi(3)-b)*m[i+1])*h*h/6.0
+                },
+                None => 0.0,
+            }).collect()
+        },
+
+        Resampling::BandLimited => {
+            let step_x = (x[n-1]-x[0])/(n-1) as f64;
+            let step_g = if grid.len() > 1 { (grid[grid.len()-1]-grid[0]).abs()/(grid.len()-1) as f64 } else { step_x };
+            let scale = (step_x/step_g).min(1.0);  // Cutoff at the coarser Nyquist frequency
+            let width = (LANCZOS/scale).ceil() as isize;
+
+            grid.iter().map(|&g| match index(x, g) {
+                Some(t) => {
+                    // Weights renormalized by those actually used: the window is cut at the ends of x
+                    let i0 = t.round() as isize;
+                    let (sum, weights) = (i0-width..=i0+width).filter(|&i| i >= 0 && (i as usize) < n).fold((0.0, 0.0), |(sum, weights), i| {
+                        let d = (t-i as f64)*scale;
+                        let w = scale*sinc(d)*if d.abs() < LANCZOS { sinc(d/LANCZOS) } else { 0.0 };
+                        (sum+y[i as usize]*w, weights+w)
+                    });
+                    if weights.abs() > 1E-12 { sum/weights } else { 0.0 }
+                },
+                None => 0.0,
+            }).collect()
+        },
+    }
+}
+
+// Fractional index of g on the ascending axis x
+fn index(x: &[f64], g: f64) -> Option<f64> {
+    let n = x.len();
+    if !(g >= x[0] && g <= x[n-1]) { return None }  // NaN too
+    let i = match x.binary_search_by(|v| v.total_cmp(&g)) {
+        Ok(i) => return Some(i as f64),
+        Err(i) => i-1,
+    };
+    Some(i as f64+(g-x[i])/(x[i+1]-x[i]))
+}
+
+// Second derivatives of the natural cubic spline through (x, y)
+fn spline(x: &[f64], y: &[f64]) -> Vec<f64> {
+    let n = x.len();
+    let mut m = vec![0.0; n];
+    let mut u = vec![0.0; n];
+
+    for i in 1..n-1 {
+        let sig = (x[i]-x[i-1])/(x[i+1]-x[i-1]);
+        let p = sig*m[i-1]+2.0;
+        m[i] = (sig-1.0)/p;
+        let d = (y[i+1]-y[i])/(x[i+1]-x[i])-(y[i]-y[i-1])/(x[i]-x[i-1]);
+        u[i] = (6.0*d/(x[i+1]-x[i-1])-sig*u[i-1])/p;
+    }
+
+    m[n-1] = 0.0;
+    for i in (0..n-1).rev() { m[i] = m[i]*m[i+1]+u[i]; }
+    m
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 { 1.0 } else { (PI*x).sin()/(PI*x) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn band_limited_keeps_a_constant_up_to_the_ends() {
+        let x: Vec<f64> = (0..200).map(|i| i as f64*0.5).collect();
+        let y = vec![2.0; x.len()];
+        for step in [0.37, 1.3].iter() {
+            let grid: Vec<f64> = (0..).map(|j| j as f64*step).take_while(|g| *g <= 99.5).collect();
+            let out = resample(&x, &y, &grid, Resampling::BandLimited);
+            assert!(out.iter().all(|v| (v-2.0).abs() < 1E-9), "{:?}", out);
+        }
+    }
+
+    #[test]
+    fn known_values() {
+        let x: Vec<f64> = (0..11).map(|i| i as f64).collect();
+        let line: Vec<f64> = x.iter().map(|x| 3.0*x-1.0).collect();
+        let grid = [0.0, 2.5, 7.25, 10.0, 10.5];
+        assert_eq!(resample(&x, &line, &grid, Resampling::Linear), vec![-1.0, 6.5, 20.75, 29.0, 0.0]);
+        // A natural spline through a straight line is the line
+        let spline = resample(&x, &line, &grid, Resampling::Spline);
+        assert!(spline[..4].iter().zip(&[-1.0, 6.5, 20.75, 29.0]).all(|(s, e)| (s-e).abs() < 1E-12));
+        // On the points of x, every method returns y
+        for method in [Resampling::Linear, Resampling::Spline, Resampling::BandLimited].iter() {
+            let out = resample(&x, &line, &x, *method);
+            assert!(out.iter().zip(&line).all(|(o, l)| (o-l).abs() < 1E-9), "{:?}", method);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// Microwave saturation and power series
+use crate::exc::{GAMMA_E};
+
+// Saturation factor s = 1/(1 + gamma^2 B1^2 T1 T2); b1 in G, t1 and t2 in us.
+// Without relaxation times (0.0) the line never saturates.
+pub fn factor(b1: f64, t1: f64, t2: f64) -> f64 {
+    1.0/(1.0 + (GAMMA_E*b1).powi(2)*t1*t2*1E-12)
+}
+
+// Double integral of a spectrum: the absorption is integrated once per harmonic
+pub fn double_integral(spec: &[f64], incr: f64, harm: usize) -> f64 {
+    let mut spec = spec.to_vec();
+    for _ in 0..harm {
+        let mut sum = 0.0;
+        for y in spec.iter_mut() { sum += *y*incr; *y = sum; }
+    }
+    spec.iter().sum::<f64>()*incr
+}
+
+// Power saturation curve I = a sqrt(P) / (1 + (2^(1/b)-1) P/P_half)^(b/2);
+// b is 1 for inhomogeneous lines and 3 for homogeneous ones
+pub fn curve(power: f64, a: f64, p_half: f64, b: f64) -> f64 {
+    a*power.sqrt()/(1.0 + (2.0_f64.powf(1.0/b)-1.0)*power/p_half).powf(b/2.0)
+}
+
+// Fit of the half-saturation power to the double integrals of a power series.
+// The amplitude is linear and solved exactly, P_half is searched on a logarithmic scale.
+// Returns (a, P_half, rms).
+pub fn fit_p_half(powers: &[f64], ints: &[f64], b: f64) -> (f64, f64, f64) {
+    let rms_at = |log_p: f64| {
+        let p_half = log_p.exp();
+        let shape: Vec<f64> = powers.iter().map(|&p| curve(p, 1.0, p_half, b)).collect();
+        let norm: f64 = shape.iter().map(|f| f*f).sum();
+        let a = if norm > 0.0 { shape.iter().zip(ints).map(|(f, y)| f*y).sum::<f64>()/norm } else { 0.0 };
+        let rms = (shape.iter().zip(ints).map(|(f, y)| (y-a*f).powi(2)).sum::<f64>()/powers.len().max(1) as f64).sqrt();
+        (a, rms)
+    };
+
+    let p_min = powers.iter().cloned().fold(f64::INFINITY, f64::min).max(1E-9);
+    let p_max = powers.iter().cloned().fold(0.0, f64::max).max(p_min);
+
+    // Coarse scan, then golden section around the best point
+    let (lo, hi) = ((p_min/100.0).ln(), (p_max*100.0).ln());
+    let steps = 200;
+    let step = (hi-lo)/steps as f64;
+    let best = (0..=steps).map(|k| lo+k as f64*step)
+        .min_by(|x, y| rms_at(*x).1.partial_cmp(&rms_at(*y).1).unwrap())
+        .unwrap();
+
+    let g = (5.0_f64.sqrt()-1.0)/2.0;
+    let (mut x_lo, mut x_hi) = (best-step, best+step);
+    for _ in 0..60 {
+        let (x1, x2) = (x_hi-g*(x_hi-x_lo), x_lo+g*(x_hi-x_lo));
+        if rms_at(x1).1 < rms_at(x2).1 { x_hi = x2 } else { x_lo = x1 }
+    }
+
+    let log_p = (x_lo+x_hi)/2.0;
+    let (amp, rms) = rms_at(log_p);
+    (amp, log_p.exp(), rms)
+}
+
+// Power saturation curve fitted to a power series
+pub struct PowerFit {
+    pub amp: f64,
+    pub p_half: f64,  // Half-saturation power (mW)
+    pub b: f64,  // Inhomogeneity parameter
+    pub rms: f64,
+}
+
+impl PowerFit {
+    pub fn report(&self, powers: &[f64], ints: &[f64]) -> String {
+        let mut text = format!("P1/2 = {:.4} mW\nb = {:.2}\nrms = {:.4e}\n\nPower (mW)\tDouble integral\tFit\n", self.p_half, self.b, self.rms);
+        for (&p, &int) in powers.iter().zip(ints) {
+            text.push_str(&format!("{:.4}\t{:.6e}\t{:.6e}\n", p, int, curve(p, self.amp, self.p_half, self.b)));
+        }
+        text
+    }
+}
+
+// Fit of P_half and b together: b is scanned between 0.5 and 3, P_half fitted at every b
+pub fn fit_power_series(powers: &[f64], ints: &[f64]) -> PowerFit {
+    (0..=250).map(|k| 0.5+0.01*k as f64)
+        .map(|b| {
+            let (amp, p_half, rms) = fit_p_half(powers, ints, b);
+            PowerFit { amp, p_half, b, rms }
+        })
+        .min_by(|x, y| x.rms.partial_cmp(&y.rms).unwrap_or(std::cmp::Ordering::Equal))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn double_integral_of_a_derivative() {
+        // First derivative of a unit-area Gaussian, sigma 1
+        let incr = 0.01;
+        let spec: Vec<f64> = (0..2001).map(|i| {
+            let x = i as f64*incr-10.0;
+            -x*(-x*x/2.0).exp()/(2.0*std::f64::consts::PI).sqrt()
+        }).collect();
+        assert!((double_integral(&spec, incr, 1)-1.0).abs() < 1E-3);
+    }
+
+    #[test]
+    fn power_series_recovers_p_half_and_b() {
+        let powers = [0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0];
+        let ints: Vec<f64> = powers.iter().map(|&p| curve(p, 3.0, 8.0, 1.5)).collect();
+        let fit = fit_power_series(&powers, &ints);
+        assert!((fit.p_half-8.0).abs() < 0.05, "{}", fit.p_half);
+        assert!((fit.b-1.5).abs() < 0.011, "{}", fit.b);
+        assert!((fit.amp-3.0).abs() < 0.05);
+    }
+}
@@ -1,217 +1,653 @@
-use crate::ent::{Radical};
+use crate::bkg::{Baseline};
+use crate::ent::{Radical, Param};
+use crate::exc;
+use crate::fft;
+use crate::flt;
+use crate::flt::{Direction};
+use crate::ham;
+use crate::ham::{Engine, HamError};
+use crate::io::{Acquisition, Spectrum};
+use crate::lsh;
+use crate::lsh::{Mode, Shape};
+use crate::pol;
+use crate::rsm;
+use crate::rsm::{Resampling};
+use crate::sat;
+use crate::sat::{PowerFit};
+use crate::stk;
+use crate::stk::{Stick};
+use num_complex::Complex64;
+use rayon::prelude::*;
 use std::sync::{Arc, Mutex};
 
+pub const UNSCORED: f64 = 1E+20;  // sigma until the current values are scored against exp
+
+// What the MC draws: the radicals and the instrument parameters with a variation
+#[derive(Clone)]
+pub struct Trial {
+    pub rads: Vec<Radical>,
+    pub phase: Param,
+    pub time_const: Param,
+    pub baseline: Baseline,
+}
+
+impl Trial {
+    fn randomize(&self) -> Trial {
+        let mut time_const = self.time_const.randomize();
+        if time_const.val < 0.0 { time_const.val = 0.0 }
+        Trial {
+            rads: self.rads.iter().map(|rad| rad.randomize()).collect(),
+            phase: self.phase.randomize(),
+            time_const,
+            baseline: self.baseline.randomize(),
+        }
+    }
+}
+
+// Everything the stick spectrum of a radical depends on, and the grid it is transformed on
+#[derive(Clone, Debug, PartialEq)]
+struct StickKey {
+    engines: Vec<Engine>,  // Of the radical and of its partner, if any
+    values: Vec<f64>,
+}
+
+// Stick spectrum of a radical and the transform of its grid, kept while the nuclei don't change
+#[derive(Clone)]
+struct Cached {
+    key: StickKey,
+    sticks: Arc<Vec<Stick>>,
+    ft: Arc<Vec<Complex64>>,
+}
+
 #[derive(Clone)]
 pub struct Simulator {
-    pub exp: Arc<Mutex<Vec<f64>>>,  // will be array
+    pub exp: Arc<Mutex<Spectrum>>,
     pub teor: Arc<Mutex<Vec<f64>>>,
-    pub points: f64,  // TODO get from exp!
+    pub points: Arc<Mutex<f64>>,  // From exp when loaded
     pub sweep: Arc<Mutex<f64>>,
+    pub center: Arc<Mutex<f64>>,  // Center field (G); 0.0 centers the sweep on the free electron
+    pub resampling: Arc<Mutex<Resampling>>,  // How exp is brought onto the simulation grid
+    pub freq: Arc<Mutex<f64>>,  // Microwave frequency (GHz)
+    pub mod_amp: Arc<Mutex<f64>>,  // Modulation amplitude (peak-to-peak); 0.0 is the ideal derivative
+    pub mode: Arc<Mutex<Mode>>,  // Spectrum output mode
+    pub phase: Arc<Mutex<Param>>,  // Detection phase error (degrees)
+    pub b1: Arc<Mutex<f64>>,  // Microwave field (G); saturates the radicals with T1 and T2
+    pub time_const: Arc<Mutex<Param>>,  // Lock-in time constant (ms); 0.0 is no filter
+    pub conv_time: Arc<Mutex<f64>>,  // Conversion time per point (ms)
+    pub direction: Arc<Mutex<Direction>>,  // Sweep direction
+    pub baseline: Arc<Mutex<Baseline>>,  // Polynomial baseline and broad background
     pub rads: Arc<Mutex<Vec<Radical>>>,
-    pub sigma: f64,  // Starts from 1E+20
-    pub iters: usize,  // MC iterations
+    pub sigma: Arc<Mutex<f64>>,  // Deviation of the current values; UNSCORED after exp or rads are replaced
+    pub iters: Arc<Mutex<usize>>,  // MC iterations
     pub mc_go: Arc<Mutex<bool>>,  // Is the MC going?
+    cache: Arc<Mutex<Vec<Option<Cached>>>>,  // Per radical (and isotopologue) stick spectra
 }
 
 impl Simulator {
     pub fn new() -> Simulator {
         Simulator {
-            exp: Arc::new(Mutex::new(Vec::new())),
+            exp: Arc::new(Mutex::new(Spectrum::default())),
             teor: Arc::new(Mutex::new(Vec::new())), // vec![0.0; self.points],
-            points: 1024.0,  // self.exp.len(),
+            points: Arc::new(Mutex::new(1024.0)),
             sweep: Arc::new(Mutex::new(100.0)),
+            center: Arc::new(Mutex::new(0.0)),
+            resampling: Arc::new(Mutex::new(Resampling::Spline)),
+            freq: Arc::new(Mutex::new(9.5)),
+            mod_amp: Arc::new(Mutex::new(0.0)),
+            mode: Arc::new(Mutex::new(Mode::FirstDerivative)),
+            phase: Arc::new(Mutex::new(Param::set(0.0, 0.0))),
+            b1: Arc::new(Mutex::new(0.0)),
+            time_const: Arc::new(Mutex::new(Param::set(0.0, 0.0))),
+            conv_time: Arc::new(Mutex::new(40.96)),
+            direction: Arc::new(Mutex::new(Direction::Up)),
+            baseline: Arc::new(Mutex::new(Baseline::default())),
             rads: Arc::new(Mutex::new(Vec::new())),
-            sigma: 1E+20,
-            iters: 0,
+            sigma: Arc::new(Mutex::new(UNSCORED)),
+            iters: Arc::new(Mutex::new(0)),
             mc_go: Arc::new(Mutex::new(false)),
+            cache: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    // Load an experimental spectrum: the simulation takes its field axis
+    pub fn set_exp(&self, exp: Spectrum) {
+        if exp.len() >= 2 {
+            *self.points.lock().unwrap() = exp.len() as f64;
+            *self.sweep.lock().unwrap() = exp.sweep();
+            *self.center.lock().unwrap() = exp.center();
+        }
+        // Without a frequency, the free electron resonance goes to the center of the sweep
+        match exp.acq.freq {
+            Some(freq) => *self.freq.lock().unwrap() = freq,
+            None if exp.len() >= 2 => *self.freq.lock().unwrap() = ham::resonance_freq(exp.center()),
+            None => (),
+        }
+        if let Some(mod_amp) = exp.acq.mod_amp { *self.mod_amp.lock().unwrap() = mod_amp; }
+        if let Some(tc) = exp.acq.time_const { self.time_const.lock().unwrap().val = tc; }
+        if let Some(conv_time) = exp.acq.conv_time { *self.conv_time.lock().unwrap() = conv_time; }
+        if let Some(direction) = exp.acq.direction { *self.direction.lock().unwrap() = direction; }
+        *self.exp.lock().unwrap() = exp;
+        *self.sigma.lock().unwrap() = UNSCORED;
+    }
+
+    pub fn points(&self) -> usize {
+        *self.points.lock().unwrap() as usize
+    }
+
+    // Simulate on any number of points; exp gets resampled
+    pub fn set_points(&self, points: usize) {
+        *self.points.lock().unwrap() = points.max(2) as f64;
+    }
+
+    // Experimental intensities on the simulation grid, resampled when the axes differ
+    pub fn exp_on_grid(&self) -> Vec<f64> {
+        let grid = self.field_axis();
+        let exp = self.exp.lock().unwrap();
+        if exp.is_empty() { return vec![0.0; grid.len()] }
+
+        if Simulator::on_grid(&exp, &grid) { return exp.int.clone() }
+
+        rsm::resample(&exp.fld, &exp.int, &grid, *self.resampling.lock().unwrap())
+    }
+
+    fn on_grid(exp: &Spectrum, grid: &[f64]) -> bool {
+        exp.fld.len() == grid.len() && exp.fld.iter().zip(grid).all(|(e, g)| (e-g).abs() < 1E-6*(1.0+g.abs()))
+    }
+
+    // Simulation grid, and how exp is mapped onto it
+    pub fn grid_report(&self) -> String {
+        let grid = self.field_axis();
+        let step = if grid.len() > 1 { grid[1]-grid[0] } else { 0.0 };
+        let mut report = format!(
+            "Grid: {} points from {:.4} to {:.4} G, step {:.6} G\n",
+            grid.len(), grid.first().unwrap_or(&0.0), grid.last().unwrap_or(&0.0), step
+        );
+
+        let exp = self.exp.lock().unwrap();
+        if !exp.is_empty() {
+            report += &match Simulator::on_grid(&exp, &grid) {
+                true => format!("Experimental: {} points on the grid\n", exp.len()),
+                false => format!(
+                    "Experimental: {} points from {:.4} to {:.4} G, resampled ({:?})\n",
+                    exp.len(), exp.fld[0], exp.fld[exp.len()-1], *self.resampling.lock().unwrap()
+                ),
+            };
         }
+        report
+    }
+
+    // Field axis of the simulation (G)
+    pub fn field_axis(&self) -> Vec<f64> {
+        let sweep = *self.sweep.lock().unwrap();
+        let points = self.points();
+        let start = self.center()-sweep/2.0;
+        (0..points).map(|j| start+j as f64*sweep/(points.max(2)-1) as f64).collect()
+    }
+
+    // Simulated spectrum of the current radicals on the field axis, with the acquisition it assumes
+    pub fn teor_spectrum(&self) -> Spectrum {
+        let rads = self.rads.lock().unwrap().clone();
+        let mut spectrum = Spectrum::new(self.field_axis(), self.calcola(rads));
+        spectrum.acq = Acquisition {
+            freq: Some(*self.freq.lock().unwrap()),
+            mod_amp: Some(*self.mod_amp.lock().unwrap()),
+            time_const: Some(self.time_const.lock().unwrap().val),
+            conv_time: Some(*self.conv_time.lock().unwrap()),
+            direction: Some(*self.direction.lock().unwrap()),
+            ..self.exp.lock().unwrap().acq.clone()
+        };
+        spectrum
+    }
+
+    // Center field, the free electron resonance if not set
+    pub fn center(&self) -> f64 {
+        match *self.center.lock().unwrap() {
+            center if center > 0.0 => center,
+            _ => ham::center_field(*self.freq.lock().unwrap()),
+        }
+    }
+
+    // Free electron resonance from the center of the sweep (G): sticks are centered on the former
+    fn offset(&self) -> f64 {
+        let freq = *self.freq.lock().unwrap();  // Released before center() locks it again
+        ham::center_field(freq)-self.center()
     }
 
     // Calculate teorical spectra
     pub fn calcola(&self, rads: Vec<Radical>) -> Vec<f64> {
-        let sweep = self.sweep.lock().unwrap();
-        let incrgauss = *sweep/(self.points -1.0);
-        let mut lno = vec![0.0; self.points as usize];
-        let mut newteor = vec![0.0; self.points as usize];
-
-        // Stickspectrum
-        for rad in rads {
-            let mut totale = 1.0;  // Total intensity
-            let mut pf = 1.0;  // Max intensity point value
-            let mut pcostanti: Vec<f64> = Vec::new();
-            let mut spini: Vec<f64> = Vec::new();
-
-            for nuc in &rad.nucs {
-                let pcostante = nuc.hpf.val/incrgauss as f64;
-                pcostanti.push(pcostante);
-                spini.push(2.0*nuc.spin.val);
-            }
+        let sweep = *self.sweep.lock().unwrap();
+        let points = self.points();
+        let mode = *self.mode.lock().unwrap();
+        let phase = self.phase.lock().unwrap().val;
+
+        let mut newteor = vec![0.0; points];
+        for part in self.components(&rads) {
+            for (teor, val) in newteor.iter_mut().zip(part) { *teor += val; }
+        }
 
-            let mut pa = 1.0;  // peak area?
-            for (i, nuc) in rad.nucs.iter().enumerate() {
-                pa = pa + pcostanti[i] * spini[i] * nuc.eqs.val;
-            }
-            if pa < self.points { pa = self.points; }
-
-            let mut intensity = vec![0.0; pa as usize];
-            intensity[1] = 1.0;  // TODO: check
-
-            for (i, nuc) in rad.nucs.iter().enumerate() {
-                let mut eq = 1;
-                while eq <= nuc.eqs.val as usize {
-                    let mut indice1 = pf as usize;
-                    while indice1 > 0 {
-                        if intensity[indice1] != 0.0 {
-                            let mut i2 = 1.0;
-                            while i2 <= (2.0*nuc.spin.val) {
-                                let new = indice1 as f64 + i2 * pcostanti[i];
-                                intensity[new as usize]+=intensity[indice1];
-                                totale+=intensity[indice1];
-
-                                i2+=1.0;
-
-                                if new > pf {
-                                    pf = new;
-                                }
-                            }  // while i2...
-
-                        }  // if intensity[indice1]...
-
-                        indice1 = indice1 - 1; // Decrement
-                    }  // while indice1...
-
-                    eq+=1;
-                }  // for(eq=1;eq<=nucleis[l][i];i1++)
-            }  // for nuc in rad.nucs
-
-            let shift: isize = ((self.points as isize)-(pf as isize))/2;
-            let shift_abs: usize = shift.abs() as usize;  // Eraseme
-
-            if shift < 0 {
-                let mut point = 1;
-                while point < self.points as usize {
-                    intensity[point] = intensity[point-shift_abs];
-                    intensity[point-shift_abs] = 0.0;
-
-                    point+=1;  // Increment
-                }  // for(i=1;i<=punti;i++)
-            } else if shift > 0 {
-                let mut point = pf as isize;
-                while point as usize >= 1 {
-                    intensity[(point as usize)+shift_abs]=intensity[(point as usize)];
-                    intensity[(point as usize)]=0.0;
-
-                    point-=1;  // Decrement
-                }  // for(i=pf;i>=1;i--)
-            }  // if shift...
-
-            // ...
-            // Stickspectrum is now stored in intensity vector;
-            // It's time for the Fourier transformation of the Stickspectrum...
-            // ... and multiplication with the Fourier transform of the lineshape function.
-
-            let mut t2 = 2.0/(3.0_f64.sqrt())*rad.lwa.val;  // Lorentzian lineshape
-
-            let mut t1 = (-0.02)*(t2.powi(3))*rad.amount.val*rad.lrtz.val /
-                (totale as f64*std::f64::consts::PI);  // Gaussian lineshape
-
-            let mut w2 = -(*sweep as f64)/2.0;
-
-            let mut point = 1;
-            while point < self.points as usize {
-                let a = w2-rad.dh1.val;
-                // Peak intensity!
-                lno[point] = (t1*a)/((1.0+t2.powi(2)*a.powi(2))*(1.0+t2.powi(2)*a.powi(2)));
-                w2 = w2 + incrgauss as f64;
-
-                point+=1;  // Increment point
-            }  // for (j=1;j<=punti;j++)
-
-            w2 = -(*sweep as f64)/2.0; // reset w2
-            t2 = 2.0/rad.lwa.val;  // change t2
-
-            t1 = -rad.amount.val*(t2.powi(3))*0.01*(100.0-rad.lrtz.val)/
-                (totale as f64*(2.0*std::f64::consts::PI).sqrt());  // 100-lorentz == gauss
-
-            let mut point = 1;
-            while point < self.points as usize {
-                let a = w2-rad.dh1.val;
-                let dd = (std::f64::consts::E).powf(-0.5*(t2.powi(2))*(a.powi(2)));
-                if dd > 1E-35 { lno[point] = lno[point] + t1*a*dd; }
-                w2 = w2 + incrgauss as f64;
-
-                point+=1;  // Increment point
-            }  // for (j=1;j<=punti;j++)
-
-            point = 1;  // Restart counter
-            while point < self.points as usize {
-                if intensity[point] != 0.0 {
-                    let mut i1 = 1;
-                    while i1 < self.points as usize {
-                        let i2: isize = (self.points as isize/2) - i1 as isize;
-                        if ((point as isize -i2) >= 1) && ((point as isize -i2) < (self.points as isize)) {
-                            newteor[(point as isize -i2 as isize) as usize]+=(lno[i1] as f64)*(intensity[point] as f64);
-                        }
-
-                        i1+=1;  // Increment 1i
-                    }  // for (i1=1;i1<=punti;i1++)
-                }  // if intensity[point]
-
-                point+=1; // Increment j
-            }  // for (j=1;j<=punti;j++)
-        }
-
-        newteor  // return
+        // Broad background goes through the lock-in like the radicals, the baseline doesn't
+        let baseline = self.baseline.lock().unwrap();
+        for (teor, broad) in newteor.iter_mut().zip(baseline.broad(sweep, points, mode, phase)) { *teor += broad; }
+        let newteor = self.filter(&newteor);
+
+        newteor.iter().zip(baseline.polynomial(points)).map(|(teor, poly)| teor+poly).collect()
     }  // fn calcola
 
-    pub fn mc_fit(&mut self) {  // TODO: CONDITIONAL REASSIGNMENT!
-        let rads = self.rads.lock().unwrap(); // RICONTROLLARE!
-        let mut newteor = self.calcola((&rads).to_vec());  // Basta prendere quello gia' calcolato, no?
-        let (mut somma, mut somma1, mut somma2): (f64, f64, f64) = (0.0, 0.0, 0.0);
-        let start: usize = 1;
-        let fine: usize = self.points as usize + 1;
-        self.iters+=1;
+    // Spectrum of each radical, lock-in filter included, without background and baseline
+    pub fn parts(&self, rads: &[Radical]) -> Vec<Vec<f64>> {
+        self.components(rads).iter().map(|part| self.filter(part)).collect()
+    }
+
+    // Spectrum of each radical before the lock-in filter
+    fn components(&self, rads: &[Radical]) -> Vec<Vec<f64>> {
+        let points = self.points();
+
+        // Saturation, then isotopologues simulated as separate weighted radicals, with their pair partner
+        let b1 = *self.b1.lock().unwrap();
+        let pairs = self.pairs(rads);
+        let isos: Vec<(usize, Radical, &[Stick])> = rads.iter().zip(pairs.iter()).enumerate()
+            .flat_map(|(r, (rad, pair))| rad.saturated(b1).isotopologues().into_iter().map(move |rad| (r, rad, &pair[..])))
+            .collect();
+
+        // Radicals are computed concurrently, then summed in their order:
+        // the result doesn't depend on the number of threads
+        let parts: Vec<Vec<f64>> = isos.par_iter().enumerate()
+            .map(|(slot, (_, rad, pair))| self.component(rad, pair, slot))
+            .collect();
+        let mut sums = vec![vec![0.0; points]; rads.len()];
+        for ((r, _, _), part) in isos.iter().zip(parts) {
+            for (sum, val) in sums[*r].iter_mut().zip(part) { *sum += val; }
+        }
+        sums
+    }
+
+    // Spectrum of a single radical; slot is its place in the cache
+    fn component(&self, rad: &Radical, pair: &[Stick], slot: usize) -> Vec<f64> {
+        let sweep = *self.sweep.lock().unwrap();
+        let points = self.points();
+        let freq = *self.freq.lock().unwrap();
+        let incrgauss = sweep/(points-1) as f64;
+        let mod_amp = *self.mod_amp.lock().unwrap();
+        let mode = *self.mode.lock().unwrap();
+        let phase = self.phase.lock().unwrap().val;
+        let mut lno = vec![0.0; 2*points-1];  // Lineshape from -sweep to +sweep
+
+        let (sticks, ft) = self.cached(rad, slot, freq, sweep);
+        if rad.exch_w.val > 0.0 { return self.exchanged(rad, &sticks, sweep) }
+
+        // Polarization changes the stick intensities, so the cached transform holds for unpolarized radicals only
+        let ft = if rad.pol_net.val == 0.0 && rad.pol_mult.val == 0.0 { ft } else {
+            let sticks = pol::polarize(&sticks, rad.dh1.val, rad.pol_net.val, rad.pol_mult.val, pair);
+            Arc::new(fft::forward(&stk::to_grid(&Simulator::shifted(&sticks, self.offset()), sweep, points), ft.len()))
+        };
+
+        // Lineshape
+        for (k, l) in lno.iter_mut().enumerate() {
+            let a = (k as f64-(points-1) as f64)*incrgauss-rad.dh1.val;
+            let line = lsh::harmonic(
+                a, rad.lwa.val+rad.lwl.val, mode.harmonic(), mod_amp, |x, deriv| rad.line(x, deriv)
+            );
+            *l = rad.amount.val * mode.mix(line, phase);
+        }
+
+        // Convolution of the stickspectrum with the lineshape: point j is at j+points-1 of the full convolution
+        fft::convolve(&ft, &lno)[points-1..2*points-1].to_vec()
+    }
+
+    // Stick spectrum and transform of its grid, from the cache if the slot holds the same nuclei
+    fn cached(&self, rad: &Radical, slot: usize, freq: f64, sweep: f64) -> (Arc<Vec<Stick>>, Arc<Vec<Complex64>>) {
+        let points = self.points();
+        let offset = self.offset();
+        let mut key = Simulator::stick_key(rad, 0.0);
+        key.values.extend_from_slice(&[points as f64, freq, sweep, offset]);
 
-        let mut exp = self.exp.lock().unwrap();
+        if let Some(Some(entry)) = self.cache.lock().unwrap().get(slot) {
+            if entry.key == key { return (entry.sticks.clone(), entry.ft.clone()) }
+        }
+
+        let sticks = Arc::new(Simulator::sticks(rad, freq, sweep+2.0*offset.abs()));
+        let grid = stk::to_grid(&Simulator::shifted(&sticks, offset), sweep, points);
+        let ft = Arc::new(fft::forward(&grid, fft::length(3*points-2)));
 
-        // Randomize Par
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() <= slot { cache.resize_with(slot+1, || None); }
+        cache[slot] = Some(Cached { key, sticks: sticks.clone(), ft: ft.clone() });
+        (sticks, ft)
+    }
 
-        // Start MC
-        for j in start..fine {
-            somma1 += newteor[j].powi(2);
-            somma2 += exp[j].abs() * newteor[j].abs();
+    fn shifted(sticks: &[Stick], offset: f64) -> Vec<Stick> {
+        sticks.iter().map(|s| Stick { pos: s.pos+offset, int: s.int }).collect()
+    }
+
+    // Everything the stick spectrum depends on (not linewidths, amount, position or polarization);
+    // dh1 counts only for a biradical partner, relative to the first electron
+    fn stick_key(rad: &Radical, dh1: f64) -> StickKey {
+        let mut values = vec![rad.nucs.len() as f64, rad.spin.val, rad.zfs_d.val, rad.zfs_e.val, rad.exch_j.val, rad.dip_r.val, dh1];
+        for nuc in &rad.nucs { values.extend_from_slice(&[nuc.spin.val, nuc.hpf.val, nuc.eqs.val]); }
+        let mut key = StickKey { engines: vec![rad.engine], values };
+        if let Some(partner) = &rad.partner {
+            let partner = Simulator::stick_key(partner, partner.dh1.val-rad.dh1.val);
+            key.engines.extend(partner.engines);
+            key.values.extend(partner.values);
         }
+        key
+    }
+
+    // Instrument response
+    fn filter(&self, spec: &[f64]) -> Vec<f64> {
+        let tc = self.time_const.lock().unwrap().val;
+        let conv_time = *self.conv_time.lock().unwrap();
+        let direction = *self.direction.lock().unwrap();
+        flt::rc(spec, tc, conv_time, direction)
+    }
+
+    // Background alone (baseline and broad signal), reported apart from the radicals
+    pub fn background(&self) -> Vec<f64> {
+        let sweep = *self.sweep.lock().unwrap();
+        let points = self.points();
+        let mode = *self.mode.lock().unwrap();
+        let phase = self.phase.lock().unwrap().val;
+        let baseline = self.baseline.lock().unwrap();
+
+        let broad = self.filter(&baseline.broad(sweep, points, mode, phase));
+        broad.iter().zip(baseline.polynomial(points)).map(|(broad, poly)| broad+poly).collect()
+    }
+
+    // Fit the polynomial baseline to the residual of the current simulation
+    pub fn fit_baseline(&self) {
+        let rads = self.rads.lock().unwrap().clone();
+        let teor = self.calcola(rads);
+        let residual: Vec<f64> = self.exp_on_grid().iter().zip(teor).map(|(exp, teor)| exp-teor).collect();
+        self.baseline.lock().unwrap().fit_polynomial(&residual);
+    }
 
-        let norma: f64;
-        if somma1 == 0.0 { norma = 0.0 } else { norma = somma2/somma1 };
+    // Spin-exchanged spectrum of a radical: Anderson's model on its sticks with the Lorentzian part of the line,
+    // then the Gaussian part as inhomogeneous broadening
+    fn exchanged(&self, rad: &Radical, sticks: &[Stick], sweep: f64) -> Vec<f64> {
+        let points = self.points();
+        let incr = sweep/(points-1) as f64;
+        let mod_amp = *self.mod_amp.lock().unwrap();
+        let mode = *self.mode.lock().unwrap();
+        let phase = self.phase.lock().unwrap().val;
+
+        let offset = self.offset();
+        let (hwhm, lw_g) = exc::widths(rad.lwa.val, rad.lrtz.val, rad.lwl.val, rad.shape == Shape::Voigt);
+        let w = rad.exch_w.val;
+
+        let spec: Vec<f64> = (0..points).map(|j| {
+            let a = j as f64*incr-sweep/2.0-rad.dh1.val-offset;
+            let line = lsh::harmonic(
+                a, 2.0/3.0_f64.sqrt()*(hwhm+w)+lw_g, mode.harmonic(), mod_amp, |x, deriv| exc::line(x, sticks, hwhm, w, deriv)
+            );
+            rad.amount.val * mode.mix(line, phase)
+        }).collect();
+
+        exc::broaden(&spec, lw_g, incr)
+    }
+
+    // Half-saturation power and inhomogeneity b from the double integrals of spectra recorded
+    // at the powers (mW), with the simulator's harmonic. Returns the fit and the double integrals.
+    pub fn power_series(&self, powers: &[f64], spectra: &[Spectrum]) -> (PowerFit, Vec<f64>) {
+        let harm = self.mode.lock().unwrap().harmonic();
+        let ints: Vec<f64> = spectra.iter().map(|spec| {
+            let incr = match (spec.fld.first(), spec.fld.last()) {
+                (Some(first), Some(last)) if spec.fld.len() > 1 => (last-first)/(spec.fld.len()-1) as f64,
+                _ => 0.0,
+            };
+            sat::double_integral(&spec.int, incr, harm)
+        }).collect();
+        (sat::fit_power_series(powers, &ints), ints)
+    }
 
-        for j in start..fine {
-            newteor[j] *= norma;
-            let diff = (exp[j] - newteor[j]).powi(2);
-            somma+=diff;
+    // Exchange frequencies of the radicals as rates, concentrations for the rate constant k_ex (M^-1 s^-1)
+    // and O2 partial pressures for the O2 solubility (M/mmHg)
+    pub fn exchange_report(&self, k_ex: f64, solubility: f64) -> String {
+        self.rads.lock().unwrap().iter().enumerate().map(|(k, rad)| {
+            let w = rad.exch_w.val;
+            format!(
+                "Radical {}: W {:.4} G, rate {:.4e} s^-1, concentration {:.4e} M, pO2 {:.2} mmHg\n",
+                k, w, exc::rate(w), exc::concentration(w, k_ex), exc::oxygen(w, k_ex, solubility)
+            )
+        }).collect()
+    }
+
+    // Stick spectrum of a single radical, centered on zero, from its engine
+    fn sticks(rad: &Radical, freq: f64, sweep: f64) -> Vec<Stick> {
+        match Simulator::engine(rad) {
+            Engine::Perturbation => stk::sticks(&rad.nucs),
+            // Too large systems are computed to first order
+            engine => ham::sticks(rad, engine, freq, sweep).unwrap_or_else(|_| stk::sticks(&rad.nucs)),
         }
+    }
 
-        let mut mc_rads = Vec::new();
-        let newsigma =(somma/(fine-start) as f64).sqrt();
+    // Engine in use: first order knows nothing of high spins, zero-field splitting or biradicals,
+    // and the zero-field splitting only shows in a powder
+    pub fn engine(rad: &Radical) -> Engine {
+        match rad.engine {
+            Engine::Perturbation if rad.zfs_d.val != 0.0 => Engine::Powder,
+            Engine::Perturbation if rad.spin.val > 0.5 || rad.partner.is_some() => Engine::Exact,
+            engine => engine,
+        }
+    }
+
+    // Radicals (by index) too large for the exact engine, simulated to first order instead
+    pub fn fallbacks(rads: &[Radical]) -> Vec<(usize, HamError)> {
+        rads.iter().enumerate()
+            .filter_map(|(k, rad)| match Simulator::engine(rad) {
+                Engine::Perturbation => None,
+                engine => ham::check(rad, engine).err().map(|error| (k, error)),
+            })
+            .collect()
+    }
 
-        for mut rad in (&rads).to_vec() {
-            rad.lwa = rad.lwa.randomize();
-            rad.amount = rad.amount.randomize();
-            rad.lrtz = rad.lrtz.randomize();
-            rad.dh1 = rad.dh1.randomize();
+    // Unpolarized sticks of the radical pair partners on the absolute axis (empty without a partner)
+    fn pairs(&self, rads: &[Radical]) -> Vec<Vec<Stick>> {
+        let sweep = *self.sweep.lock().unwrap();
+        let freq = *self.freq.lock().unwrap();
+
+        rads.iter().map(|rad| match rad.pol_pair.and_then(|k| rads.get(k)) {
+            Some(partner) => partner.isotopologues().iter().flat_map(|iso| {
+                let weight = if partner.amount.val > 0.0 { iso.amount.val/partner.amount.val } else { 0.0 };
+                Simulator::sticks(iso, freq, sweep).into_iter()
+                    .map(move |s| Stick { pos: s.pos+iso.dh1.val, int: s.int*weight })
+                    .collect::<Vec<Stick>>()
+            }).collect(),
+            None => Vec::new(),
+        }).collect()
+    }
 
-            for mut nuc in rad.nucs.clone() {
-                nuc.hpf = nuc.hpf.randomize();
+    // Stick spectrum of all the radicals on the field axis (G): positions include dh1,
+    // intensities the amounts and the polarization
+    pub fn stick_spectrum(&self, rads: Vec<Radical>) -> Vec<Stick> {
+        let sweep = *self.sweep.lock().unwrap();
+        let freq = *self.freq.lock().unwrap();
+        let pairs = self.pairs(&rads);
+        let offset = self.offset()+self.center();
+        let mut sticks = Vec::new();
+
+        for (rad, pair) in rads.iter().zip(pairs.iter()) {
+            for iso in rad.isotopologues() {
+                let polarized = pol::polarize(
+                    &Simulator::sticks(&iso, freq, sweep), iso.dh1.val, iso.pol_net.val, iso.pol_mult.val, pair
+                );
+                for stick in polarized {
+                    sticks.push(Stick { pos: stick.pos+iso.dh1.val+offset, int: stick.int*iso.amount.val });
+                }
             }
+        }
+
+        stk::merge(sticks)
+    }
+
+    // Standard deviation from the experimental spectrum, after scaling the simulation onto it
+    fn deviation(exp: &[f64], teor: &[f64]) -> f64 {
+        let somma1: f64 = teor.iter().map(|t| t.powi(2)).sum();
+        let somma2: f64 = exp.iter().zip(teor).map(|(e, t)| e*t).sum();
+        let norma = if somma1 == 0.0 { 0.0 } else { somma2/somma1 };
+        let somma: f64 = exp.iter().zip(teor).map(|(e, t)| (e-norma*t).powi(2)).sum();
+        (somma/exp.len().max(1) as f64).sqrt()
+    }
 
-            rad = Radical::check_pars(rad);  // is this legal?
-            mc_rads.push(rad);
-        }  // for rad
+    // Deviation of the current values, which the MC trials have to beat
+    pub fn score(&self) -> f64 {
+        let rads = self.rads.lock().unwrap().clone();
+        let sigma = Simulator::deviation(&self.exp_on_grid(), &self.calcola(rads));
+        *self.sigma.lock().unwrap() = sigma;
+        sigma
+    }
+
+    // Current values of what the MC draws
+    pub fn trial(&self) -> Trial {
+        Trial {
+            rads: self.rads.lock().unwrap().clone(),
+            phase: self.phase.lock().unwrap().clone(),
+            time_const: self.time_const.lock().unwrap().clone(),
+            baseline: self.baseline.lock().unwrap().clone(),
+        }
+    }
+
+    fn set_trial(&self, trial: Trial) {
+        *self.rads.lock().unwrap() = trial.rads;
+        *self.phase.lock().unwrap() = trial.phase;
+        *self.time_const.lock().unwrap() = trial.time_const;
+        *self.baseline.lock().unwrap() = trial.baseline;
+    }
+
+    // Copy with the values of a trial, sharing the spectra.
+    // The trial reads the stick cache but writes to a copy of it: with hyperfine variations every trial
+    // has sticks of its own, and storing them would evict the current ones the next trials need.
+    fn with_trial(&self, trial: &Trial) -> Simulator {
+        Simulator {
+            rads: Arc::new(Mutex::new(trial.rads.clone())),
+            phase: Arc::new(Mutex::new(trial.phase.clone())),
+            time_const: Arc::new(Mutex::new(trial.time_const.clone())),
+            baseline: Arc::new(Mutex::new(trial.baseline.clone())),
+            cache: Arc::new(Mutex::new(self.cache.lock().unwrap().clone())),
+            ..self.clone()
+        }
+    }
 
-        // Conditional reassignment
-        self.sigma = newsigma;
+    // Deviations of a batch of trials, evaluated on the thread pool
+    pub fn evaluate(&self, trials: &[Trial]) -> Vec<f64> {
+        let exp = self.exp_on_grid();
+        trials.par_iter().map(|trial| Simulator::deviation(&exp, &self.with_trial(trial).calcola(trial.rads.clone()))).collect()
+    }
 
-        self.calcola((&rads).to_vec());
-    }  // mc
+    // MC step over a batch of trials: the best one replaces the current values if it improves sigma.
+    // Trials are drawn sequentially, so the outcome doesn't depend on the number of threads.
+    pub fn mc_batch(&self, size: usize) -> bool {
+        if *self.sigma.lock().unwrap() >= UNSCORED { self.score(); }
+        let current = self.trial();
+        let trials: Vec<Trial> = (0..size).map(|_| current.randomize()).collect();
+        let sigmas = self.evaluate(&trials);
+        *self.iters.lock().unwrap() += size;
+
+        // A trial out of the lineshape's domain (e.g. zero width) simulates to NaN: never the best
+        let best = sigmas.iter().enumerate().filter(|(_, s)| s.is_finite())
+            .min_by(|a, b| a.1.partial_cmp(b.1).unwrap()).map(|(k, s)| (k, *s));
+        let sigma = *self.sigma.lock().unwrap();
+        match best {
+            Some((k, best)) if best < sigma => {
+                *self.sigma.lock().unwrap() = best;
+                self.set_trial(trials[k].clone());
+                // The polynomial is linear in its coefficients: fitted directly rather than drawn, and kept if it helps
+                let baseline = self.baseline.lock().unwrap().clone();
+                if !baseline.poly.is_empty() {
+                    self.fit_baseline();
+                    let rads = self.rads.lock().unwrap().clone();
+                    let refit = Simulator::deviation(&self.exp_on_grid(), &self.calcola(rads));
+                    if refit < best { *self.sigma.lock().unwrap() = refit } else { *self.baseline.lock().unwrap() = baseline }
+                }
+                true
+            },
+            _ => false,
+        }
+    }
 
 }  // impl Simulator
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mc_batch_only_improves() {
+        let sim = Simulator::new();
+        sim.set_points(256);
+        let exp = sim.calcola(vec![Radical::electron()]);
+        sim.set_exp(Spectrum::new(sim.field_axis(), exp));
+
+        let mut rad = Radical::electron();
+        rad.lwa = Param::set(2.0, 1.0);
+        *sim.rads.lock().unwrap() = vec![rad];
+        *sim.phase.lock().unwrap() = Param::set(0.0, 5.0);
+        let mut last = *sim.sigma.lock().unwrap();
+        for _ in 0..5 {
+            sim.mc_batch(8);
+            let sigma = *sim.sigma.lock().unwrap();
+            assert!(sigma <= last);
+            last = sigma;
+        }
+        assert!(last.is_finite());
+        assert_eq!(*sim.iters.lock().unwrap(), 40);
+    }
+
+    #[test]
+    fn exact_model_survives() {
+        let sim = Simulator::new();
+        sim.set_points(256);
+        let mut rad = Radical::electron();
+        rad.lwa = Param::set(0.5, 0.1);
+        let exp = sim.calcola(vec![rad.clone()]);
+        *sim.rads.lock().unwrap() = vec![rad];
+
+        // A stale sigma from before the experiment is not what the trials compete with
+        *sim.sigma.lock().unwrap() = 5.0;
+        sim.set_exp(Spectrum::new(sim.field_axis(), exp));
+        assert_eq!(*sim.sigma.lock().unwrap(), UNSCORED);
+
+        assert!(!sim.mc_batch(8));
+        assert!(*sim.sigma.lock().unwrap() < 1E-12);
+        assert_eq!(sim.rads.lock().unwrap()[0].lwa.val, 0.5);
+    }
+
+    #[test]
+    fn stick_cache() {
+        let sim = Simulator::new();
+        sim.set_points(256);
+        let rads = vec![Radical::probe(), Radical::electron()];
+        let first = sim.calcola(rads.clone());
+        let cached = sim.calcola(rads.clone());
+        assert_eq!(first, cached);
+
+        // A new simulator starts with an empty cache
+        let fresh = |rads: Vec<Radical>| { let sim = Simulator::new(); sim.set_points(256); sim.calcola(rads) };
+        assert_eq!(cached, fresh(rads.clone()));
+
+        // A nucleus change invalidates the entry
+        let mut changed = rads.clone();
+        changed[0].nucs[0].hpf.val = 15.0;
+        let recomputed = sim.calcola(changed.clone());
+        assert_ne!(recomputed, cached);
+        assert_eq!(recomputed, fresh(changed));
+
+        // Trials with other nuclei leave the current entries in place
+        *sim.rads.lock().unwrap() = rads.clone();
+        sim.calcola(rads.clone());
+        let before: Vec<StickKey> = sim.cache.lock().unwrap().iter().flatten().map(|entry| entry.key.clone()).collect();
+        let mut trial = sim.trial();
+        trial.rads[0].nucs[0].hpf.val = 13.0;
+        sim.evaluate(&[trial]);
+        let after: Vec<StickKey> = sim.cache.lock().unwrap().iter().flatten().map(|entry| entry.key.clone()).collect();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn no_frequency_centers_the_sweep() {
+        let sim = Simulator::new();
+        let fld: Vec<f64> = (0..101).map(|j| 3300.0+j as f64).collect();
+        sim.set_exp(Spectrum::new(fld, vec![0.0; 101]));
+        assert!(sim.offset().abs() < 1E-9);
+        assert!((*sim.freq.lock().unwrap()-9.388).abs() < 1E-3);
+    }
+}
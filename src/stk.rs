@@ -0,0 +1,129 @@
+use serde::{Serialize, Deserialize};
+use crate::ent::{Nucleus};
+
+// Stick spectrum line
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Stick {
+    pub pos: f64,  // Position from the center (G)
+    pub int: f64,  // Intensity
+}
+
+// Lines closer than this are merged
+const MERGE_TOL: f64 = 1E-9;
+
+// Splitting pattern of n equivalent nuclei: multinomial coefficients of (1 + x + ... + x^2I)^n
+pub fn pattern(spin: f64, hpf: f64, eqs: usize) -> Vec<Stick> {
+    let mult = (2.0*spin).round() as usize + 1;
+    let mut coeffs = vec![1.0];
+
+    for _ in 0..eqs {
+        let mut new_coeffs = vec![0.0; coeffs.len()+mult-1];
+        for (i, c) in coeffs.iter().enumerate() {
+            for new_c in new_coeffs[i..i+mult].iter_mut() { *new_c += c; }
+        }
+        coeffs = new_coeffs;
+    }
+
+    let m_max = (coeffs.len()-1) as f64/2.0;  // n*I
+    coeffs.iter().enumerate().map(|(k, c)| Stick { pos: hpf*(k as f64-m_max), int: *c }).collect()
+}
+
+// Combine two stick spectra (every line of a split by every line of b)
+pub fn combine(a: &[Stick], b: &[Stick]) -> Vec<Stick> {
+    let mut sticks = Vec::with_capacity(a.len()*b.len());
+    for sa in a {
+        for sb in b {
+            sticks.push(Stick { pos: sa.pos+sb.pos, int: sa.int*sb.int });
+        }
+    }
+    merge(sticks)
+}
+
+// Sort by position and sum coincident lines
+pub fn merge(mut sticks: Vec<Stick>) -> Vec<Stick> {
+    sticks.sort_by(|a, b| a.pos.partial_cmp(&b.pos).unwrap());
+
+    let mut merged: Vec<Stick> = Vec::with_capacity(sticks.len());
+    for stick in sticks {
+        match merged.last_mut() {
+            Some(last) if (stick.pos-last.pos).abs() < MERGE_TOL => last.int += stick.int,
+            _ => merged.push(stick),
+        }
+    }
+    merged
+}
+
+// Stick spectrum of a set of nuclei, centered on zero, with unitary total intensity
+pub fn sticks(nucs: &[Nucleus]) -> Vec<Stick> {
+    let mut sticks = vec![Stick { pos: 0.0, int: 1.0 }];
+
+    for nuc in nucs {
+        let eqs = nuc.eqs.val.round().max(0.0) as usize;
+        sticks = combine(&sticks, &pattern(nuc.spin.val, nuc.hpf.val, eqs));
+    }
+
+    let total: f64 = sticks.iter().map(|s| s.int).sum();
+    for stick in sticks.iter_mut() { stick.int /= total; }
+    sticks
+}
+
+// Distribute the sticks onto the field points (linear interpolation between neighbours);
+// the axis goes from -sweep/2 to sweep/2; lines out of the sweep are dropped.
+pub fn to_grid(sticks: &[Stick], sweep: f64, points: usize) -> Vec<f64> {
+    let mut grid = vec![0.0; points];
+    if points < 2 { return grid }
+    let incr = sweep/(points-1) as f64;
+
+    for stick in sticks {
+        let idx = (stick.pos+sweep/2.0)/incr;
+        let i0 = idx.floor();
+        let frac = idx-i0;
+        let i0 = i0 as isize;
+
+        if i0 >= 0 && (i0 as usize) < points { grid[i0 as usize] += (1.0-frac)*stick.int; }
+        if i0+1 >= 0 && ((i0+1) as usize) < points { grid[(i0+1) as usize] += frac*stick.int; }
+    }
+
+    grid
+}
+
+// Two columns: position and intensity
+pub fn to_ascii(sticks: &[Stick]) -> String {
+    sticks.iter().map(|s| format!("{:.6}\t{:.8e}\n", s.pos, s.int)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ints(sticks: &[Stick]) -> Vec<f64> {
+        sticks.iter().map(|s| s.int).collect()
+    }
+
+    #[test]
+    fn multinomial_patterns() {
+        assert_eq!(ints(&pattern(0.5, 2.0, 2)), vec![1.0, 2.0, 1.0]);
+        assert_eq!(ints(&pattern(1.0, 15.0, 1)), vec![1.0, 1.0, 1.0]);
+        assert_eq!(ints(&pattern(1.0, 1.0, 2)), vec![1.0, 2.0, 3.0, 2.0, 1.0]);
+        let positions: Vec<f64> = pattern(0.5, 2.0, 3).iter().map(|s| s.pos).collect();
+        assert_eq!(positions, vec![-3.0, -1.0, 1.0, 3.0]);
+    }
+
+    #[test]
+    fn sticks_of_nitroxide() {
+        // 14N triplet, each line split 1:2:1 by two protons; lines overlapping exactly are merged
+        let nucs = vec![Nucleus::set(1.0, 15.0, 1.0), Nucleus::set(0.5, 15.0, 2.0)];
+        let sticks = sticks(&nucs);
+        assert_eq!(sticks.len(), 5);
+        assert!((ints(&sticks).iter().sum::<f64>()-1.0).abs() < 1E-12);
+        let expected = [1.0, 3.0, 4.0, 3.0, 1.0];
+        for (stick, e) in sticks.iter().zip(expected.iter()) { assert!((stick.int-e/12.0).abs() < 1E-12); }
+    }
+
+    #[test]
+    fn grid_keeps_the_intensity() {
+        let sticks = vec![Stick { pos: -3.3, int: 0.25 }, Stick { pos: 1.71, int: 0.75 }, Stick { pos: 60.0, int: 1.0 }];
+        let grid = to_grid(&sticks, 20.0, 101);
+        assert!((grid.iter().sum::<f64>()-1.0).abs() < 1E-12);
+    }
+}
@@ -1,2 +1,3 @@
 pub mod ui;
 pub mod settings;
+pub mod simulation;
@@ -9,6 +9,8 @@ use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use crate::ent::{Radical};
+use crate::ham::{Engine};
+use crate::lsh::{Shape};
 
 pub struct EntryPar { buffer: gtk::EntryBuffer, widget: gtk::Entry }
 
@@ -23,12 +25,35 @@ impl EntryPar {
     }
 }
 
+// Choice among items, sent as the index of the item like a numeric parameter
+fn combo_par(
+    items: &[&str],
+    active: u32,
+    field: &'static str,
+    rad_idx: usize,
+    radpar_sender: glib::Sender<(usize, String, String, f64)>,
+    pos: (gtk::Grid, i32, i32),
+) -> gtk::ComboBoxText {
+    let widget = gtk::ComboBoxText::new();
+    for item in items { widget.append_text(item); }
+    widget.set_active(Some(active));
+    pos.0.attach(&widget, pos.1, pos.2, 1, 1);
+
+    widget.connect_changed(move |combo| {
+        if let Some(idx) = combo.get_active() {
+            radpar_sender.send((rad_idx, String::from(field), String::from("val"), idx as f64)).unwrap();
+        }
+    });
+    widget
+}
+
 pub struct Content { pub rad_box: gtk::Box }
 
 impl Content {
     fn new(
         rad_idx: usize,
         rad: &Radical,
+        n_rads: usize,
         // rad_idx, field, subfield, new value
         radpar_sender: glib::Sender<(usize, String, String, f64)>,
         // rad_idx, nuc_idx, field, subfield, new value
@@ -45,6 +70,17 @@ impl Content {
             ("dh1", "val"), ("dh1", "var"),
             ("lwa", "val"), ("lwa", "var"),
             ("lrtz", "val"), ("lrtz", "var"),
+            ("lwl", "val"), ("lwl", "var"),
+            ("spin", "val"),
+            ("zfs_d", "val"), ("zfs_d", "var"),
+            ("zfs_e", "val"), ("zfs_e", "var"),
+            ("exch_w", "val"), ("exch_w", "var"),
+            ("t1", "val"), ("t1", "var"),
+            ("t2", "val"), ("t2", "var"),
+            ("pol_net", "val"), ("pol_net", "var"),
+            ("pol_mult", "val"), ("pol_mult", "var"),
+            ("exch_j", "val"), ("exch_j", "var"),
+            ("dip_r", "val"), ("dip_r", "var"),
             ];
 
         for par_name in radpar_names.iter() {
@@ -55,6 +91,17 @@ impl Content {
                "dh1" => (2, &rad.dh1),
                "lwa" => (3, &rad.lwa),
                "lrtz" => (4, &rad.lrtz),
+               "lwl" => (5, &rad.lwl),
+               "spin" => (6, &rad.spin),
+               "zfs_d" => (7, &rad.zfs_d),
+               "zfs_e" => (8, &rad.zfs_e),
+               "exch_w" => (9, &rad.exch_w),
+               "t1" => (10, &rad.t1),
+               "t2" => (11, &rad.t2),
+               "pol_net" => (12, &rad.pol_net),
+               "pol_mult" => (13, &rad.pol_mult),
+               "exch_j" => (16, &rad.exch_j),
+               "dip_r" => (17, &rad.dip_r),
                _ => panic!("unknown field"),
            };
 
@@ -95,6 +142,49 @@ impl Content {
             }); // Connect changed
         }  // for radpar name in radpas_names
 
+        // Lineshape model
+        let shape = if rad.shape == Shape::Voigt { 1 } else { 0 };
+        combo_par(&["Pseudo-Voigt", "Voigt"], shape, "shape", rad_idx, radpar_sender.clone(), (rad_grid.clone(), 1, 14));
+
+        // Simulation engine; first order turns exact by itself for S > 1/2 or D != 0
+        let engine = match rad.engine { Engine::Perturbation => 0, Engine::Exact => 1, Engine::Powder => 2 };
+        combo_par(&["Perturbation", "Exact (solution)", "Powder"], engine, "engine", rad_idx, radpar_sender.clone(), (rad_grid.clone(), 1, 15));
+
+        // Biradical partner: none, a free electron or a copy of another radical,
+        // sent as 0, 1 and k+2 for radical k
+        let partner = gtk::ComboBoxText::new();
+        partner.append(Some("0"), "None");
+        partner.append(Some("1"), "Electron");
+        for k in (0..n_rads).filter(|&k| k != rad_idx) {
+            partner.append(Some(&(k+2).to_string()), &format!("Copy of Radical {}", k));
+        }
+        if rad.partner.is_some() { partner.append(Some("current"), "Current partner"); }
+        partner.set_active_id(Some(if rad.partner.is_some() { "current" } else { "0" }));
+        rad_grid.attach(&partner, 1, 18, 1, 1);
+
+        let radpar_sender_clone = radpar_sender.clone();
+        partner.connect_changed(move |combo| {
+            if let Some(code) = combo.get_active_id().and_then(|id| id.as_str().parse::<f64>().ok()) {
+                radpar_sender_clone.send((rad_idx, String::from("partner"), String::from("val"), code)).unwrap();
+            }
+        });
+
+        // Radical pair partner of the multiplet polarization, sent as k+1 for radical k (0 for none)
+        let pol_pair = gtk::ComboBoxText::new();
+        pol_pair.append(Some("0"), "None");
+        for k in (0..n_rads).filter(|&k| k != rad_idx) {
+            pol_pair.append(Some(&(k+1).to_string()), &format!("Radical {}", k));
+        }
+        pol_pair.set_active_id(Some(&rad.pol_pair.map_or(0, |k| k+1).to_string()));
+        rad_grid.attach(&pol_pair, 1, 19, 1, 1);
+
+        let radpar_sender_clone = radpar_sender.clone();
+        pol_pair.connect_changed(move |combo| {
+            if let Some(code) = combo.get_active_id().and_then(|id| id.as_str().parse::<f64>().ok()) {
+                radpar_sender_clone.send((rad_idx, String::from("pol_pair"), String::from("val"), code)).unwrap();
+            }
+        });
+
         // Nucs
         for (nuc_idx, nuc) in rad.nucs.iter().enumerate() {
             let nucpar_names = [
@@ -212,7 +302,7 @@ impl Settings {
 
         for (idx, rad) in rads_guard_clone.iter().enumerate() {
             let tab = self.new_rad_tab(idx, radgen_sender.clone(), refresh_settings_sender.clone());
-            let content = Content::new(idx, rad, radpar_sender.clone(), nucpar_sender.clone());
+            let content = Content::new(idx, rad, rads_guard_clone.len(), radpar_sender.clone(), nucpar_sender.clone());
             notebook.append_page(&content.rad_box, Some(&tab));
         }
 
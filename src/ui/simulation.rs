@@ -0,0 +1,249 @@
+extern crate gtk;
+
+use gtk::prelude::*;
+use std::sync::{Arc, Mutex};
+
+use crate::bkg::{Baseline, Broad, BroadShape};
+use crate::ent::{Param};
+use crate::flt::{Direction};
+use crate::lsh::{Mode};
+use crate::rsm::{Resampling};
+use crate::sim::{Simulator};
+
+// Numeric entry calling set with every value that parses
+fn entry_par<F: Fn(f64) + 'static>(val: f64, set: F, pos: (&gtk::Grid, i32, i32)) -> gtk::Entry {
+    let widget = gtk::Entry::new();
+    widget.set_text(&val.to_string());
+    pos.0.attach(&widget, pos.1, pos.2, 1, 1);
+
+    widget.connect_changed(move |entry| {
+        if let Ok(val) = entry.get_text().as_str().parse::<f64>() { set(val); }
+    });
+    widget
+}
+
+// Label, value and variation of a fitted parameter
+fn param_row(label: &str, par: Arc<Mutex<Param>>, grid: &gtk::Grid, row: i32) {
+    let (val, var) = { let par = par.lock().unwrap(); (par.val, par.var) };
+    grid.attach(&gtk::Label::new(Some(label)), 0, row, 1, 1);
+    let val_par = Arc::clone(&par);
+    entry_par(val, move |val| val_par.lock().unwrap().val = val, (grid, 1, row));
+    entry_par(var, move |var| par.lock().unwrap().var = var, (grid, 2, row));
+}
+
+// Parameter of the broad background, set only while there is one
+fn broad_row<F: Fn(&mut Broad) -> &mut Param + Copy + 'static>(
+    label: &str,
+    init: f64,
+    baseline: Arc<Mutex<Baseline>>,
+    par: F,
+    grid: &gtk::Grid,
+    row: i32,
+) -> gtk::Entry {
+    let (val, var) = match &mut baseline.lock().unwrap().broad {
+        Some(broad) => (par(broad).val, par(broad).var),
+        None => (init, 0.0),
+    };
+    grid.attach(&gtk::Label::new(Some(label)), 0, row, 1, 1);
+    let val_baseline = Arc::clone(&baseline);
+    let entry = entry_par(val, move |val| {
+        if let Some(broad) = &mut val_baseline.lock().unwrap().broad { par(broad).val = val; }
+    }, (grid, 1, row));
+    entry_par(var, move |var| {
+        if let Some(broad) = &mut baseline.lock().unwrap().broad { par(broad).var = var; }
+    }, (grid, 2, row));
+    entry
+}
+
+// Label and value of a fixed setting
+fn value_row(label: &str, value: Arc<Mutex<f64>>, grid: &gtk::Grid, row: i32) {
+    let val = *value.lock().unwrap();
+    grid.attach(&gtk::Label::new(Some(label)), 0, row, 1, 1);
+    entry_par(val, move |val| *value.lock().unwrap() = val, (grid, 1, row));
+}
+
+// Settings of the whole simulation rather than of a radical: instrument and acquisition;
+// drawing_area shows the spectrum again after the changes that need it
+pub struct SimSettings { pub window: gtk::Window }
+
+impl SimSettings {
+    pub fn new(sim: &Simulator, drawing_area: &gtk::DrawingArea) -> Self {
+        let window = gtk::Window::new(gtk::WindowType::Toplevel);
+        window.set_title("g Factor - Simulation");
+        window.set_position(gtk::WindowPosition::Center);
+
+        let grid = gtk::Grid::new();
+        grid.set_row_spacing(6);
+        grid.set_column_spacing(6);
+        grid.set_border_width(6);
+        grid.attach(&gtk::Label::new(Some("Value")), 1, 0, 1, 1);
+        grid.attach(&gtk::Label::new(Some("Variation")), 2, 0, 1, 1);
+
+        value_row("Microwave Frequency (GHz)", Arc::clone(&sim.freq), &grid, 1);
+        param_row("Time Constant (ms)", Arc::clone(&sim.time_const), &grid, 2);
+        value_row("Conversion Time (ms)", Arc::clone(&sim.conv_time), &grid, 3);
+
+        grid.attach(&gtk::Label::new(Some("Sweep Direction")), 0, 4, 1, 1);
+        let direction = gtk::ComboBoxText::new();
+        direction.append(Some("up"), "Up");
+        direction.append(Some("down"), "Down");
+        direction.set_active_id(Some(match *sim.direction.lock().unwrap() {
+            Direction::Up => "up",
+            Direction::Down => "down",
+        }));
+        let sim_direction = Arc::clone(&sim.direction);
+        direction.connect_changed(move |combo| {
+            match combo.get_active_id().as_ref().map(|id| id.as_str()) {
+                Some("down") => *sim_direction.lock().unwrap() = Direction::Down,
+                Some(_) => *sim_direction.lock().unwrap() = Direction::Up,
+                None => (),
+            }
+        });
+        grid.attach(&direction, 1, 4, 1, 1);
+
+        value_row("Microwave B1 (G)", Arc::clone(&sim.b1), &grid, 5);
+        param_row("Phase (deg)", Arc::clone(&sim.phase), &grid, 6);
+
+        // Output mode: the spectrum is simulated again
+        grid.attach(&gtk::Label::new(Some("Mode")), 0, 7, 1, 1);
+        let mode = gtk::ComboBoxText::new();
+        for (id, text) in ["absorption", "first", "second", "dispersion"].iter()
+            .zip(["Absorption", "First Derivative", "Second Derivative", "Dispersion"].iter()) {
+            mode.append(Some(id), text);
+        }
+        mode.set_active_id(Some(match *sim.mode.lock().unwrap() {
+            Mode::Absorption => "absorption",
+            Mode::FirstDerivative => "first",
+            Mode::SecondDerivative => "second",
+            Mode::Dispersion => "dispersion",
+        }));
+        let mode_sim = sim.clone();
+        let da = drawing_area.clone();
+        mode.connect_changed(move |combo| {
+            let mode = match combo.get_active_id().as_ref().map(|id| id.as_str()) {
+                Some("absorption") => Mode::Absorption,
+                Some("second") => Mode::SecondDerivative,
+                Some("dispersion") => Mode::Dispersion,
+                Some(_) => Mode::FirstDerivative,
+                None => return,
+            };
+            *mode_sim.mode.lock().unwrap() = mode;
+            let rads = mode_sim.rads.lock().unwrap().clone();
+            *mode_sim.teor.lock().unwrap() = mode_sim.calcola(rads);
+            da.queue_draw();
+        });
+        grid.attach(&mode, 1, 7, 1, 1);
+        value_row("Modulation Amplitude (G)", Arc::clone(&sim.mod_amp), &grid, 8);
+
+        // Baseline: polynomial order, broad background, and the fitted coefficients
+        grid.attach(&gtk::Label::new(Some("Baseline")), 0, 9, 1, 1);
+        let order = gtk::ComboBoxText::new();
+        for (id, text) in ["none", "0", "1", "2", "3"].iter().zip(["None", "Constant", "Linear", "Quadratic", "Cubic"].iter()) {
+            order.append(Some(id), text);
+        }
+        let poly_len = sim.baseline.lock().unwrap().poly.len();
+        order.set_active_id(Some(&poly_len.checked_sub(1).map_or("none".to_string(), |order| order.to_string())));
+        grid.attach(&order, 1, 9, 1, 1);
+
+        grid.attach(&gtk::Label::new(Some("Broad Background")), 0, 10, 1, 1);
+        let shape = gtk::ComboBoxText::new();
+        shape.append(Some("none"), "None");
+        shape.append(Some("lorentzian"), "Lorentzian");
+        shape.append(Some("gaussian"), "Gaussian");
+        shape.set_active_id(Some(match sim.baseline.lock().unwrap().broad.as_ref().map(|broad| broad.shape) {
+            Some(BroadShape::Lorentzian) => "lorentzian",
+            Some(BroadShape::Gaussian) => "gaussian",
+            None => "none",
+        }));
+        grid.attach(&shape, 1, 10, 1, 1);
+
+        let sweep = *sim.sweep.lock().unwrap();
+        let amount = broad_row("Broad Amount", 1.0, Arc::clone(&sim.baseline), |broad| &mut broad.amount, &grid, 11);
+        let center = broad_row("Broad Center (G)", 0.0, Arc::clone(&sim.baseline), |broad| &mut broad.center, &grid, 12);
+        let lw = broad_row("Broad Width (G)", sweep/2.0, Arc::clone(&sim.baseline), |broad| &mut broad.lw, &grid, 13);
+
+        let fit = gtk::Button::with_label("Fit Baseline");
+        grid.attach(&fit, 1, 14, 1, 1);
+        let report = gtk::Label::new(Some(&sim.baseline.lock().unwrap().report()));
+        report.set_selectable(true);
+        grid.attach(&report, 0, 15, 3, 1);
+
+        let baseline = Arc::clone(&sim.baseline);
+        order.connect_changed(move |combo| {
+            let order = combo.get_active_id().and_then(|id| id.as_str().parse::<usize>().ok());
+            baseline.lock().unwrap().poly.resize(order.map_or(0, |order| order+1), Param::set(0.0, 0.0));
+        });
+
+        let baseline = Arc::clone(&sim.baseline);
+        shape.connect_changed(move |combo| {
+            let shape = match combo.get_active_id().as_ref().map(|id| id.as_str()) {
+                Some("lorentzian") => BroadShape::Lorentzian,
+                Some("gaussian") => BroadShape::Gaussian,
+                _ => { baseline.lock().unwrap().broad = None; return },
+            };
+            let mut baseline = baseline.lock().unwrap();
+            match &mut baseline.broad {
+                Some(broad) => broad.shape = shape,
+                None => {
+                    let val = |entry: &gtk::Entry| entry.get_text().as_str().parse::<f64>().unwrap_or(0.0);
+                    *baseline = baseline.clone().with_broad(shape, val(&amount), val(&center), val(&lw));
+                },
+            }
+        });
+
+        let fit_sim = sim.clone();
+        fit.connect_clicked(move |_| {
+            fit_sim.fit_baseline();
+            report.set_text(&fit_sim.baseline.lock().unwrap().report());
+        });
+
+        // Simulation grid and how exp is brought onto it, with the report kept up to date
+        let grid_report = gtk::Label::new(Some(sim.grid_report().trim_end()));
+        grid_report.set_selectable(true);
+        grid.attach(&grid_report, 0, 19, 3, 1);
+
+        grid.attach(&gtk::Label::new(Some("Points")), 0, 16, 1, 1);
+        let (points_sim, points_report) = (sim.clone(), grid_report.clone());
+        entry_par(sim.points() as f64, move |points| {
+            if points >= 2.0 && points.fract() == 0.0 {
+                points_sim.set_points(points as usize);
+                points_report.set_text(points_sim.grid_report().trim_end());
+            }
+        }, (&grid, 1, 16));
+
+        grid.attach(&gtk::Label::new(Some("Sweep (G)")), 0, 17, 1, 1);
+        let (sweep_sim, sweep_report) = (sim.clone(), grid_report.clone());
+        entry_par(*sim.sweep.lock().unwrap(), move |sweep| {
+            if sweep > 0.0 {
+                *sweep_sim.sweep.lock().unwrap() = sweep;
+                sweep_report.set_text(sweep_sim.grid_report().trim_end());
+            }
+        }, (&grid, 1, 17));
+
+        grid.attach(&gtk::Label::new(Some("Resampling")), 0, 18, 1, 1);
+        let resampling = gtk::ComboBoxText::new();
+        resampling.append(Some("linear"), "Linear");
+        resampling.append(Some("spline"), "Cubic Spline");
+        resampling.append(Some("band"), "Band-Limited");
+        resampling.set_active_id(Some(match *sim.resampling.lock().unwrap() {
+            Resampling::Linear => "linear",
+            Resampling::Spline => "spline",
+            Resampling::BandLimited => "band",
+        }));
+        let sim = sim.clone();
+        resampling.connect_changed(move |combo| {
+            let method = match combo.get_active_id().as_ref().map(|id| id.as_str()) {
+                Some("linear") => Resampling::Linear,
+                Some("band") => Resampling::BandLimited,
+                Some(_) => Resampling::Spline,
+                None => return,
+            };
+            *sim.resampling.lock().unwrap() = method;
+            grid_report.set_text(sim.grid_report().trim_end());
+        });
+        grid.attach(&resampling, 1, 18, 1, 1);
+
+        window.add(&grid);
+        Self { window }
+    }
+}
@@ -6,28 +6,44 @@ use glib::clone;
 use gtk::prelude::*;
 // use gio::prelude::*;
 
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::Arc;
-
-use crate::io::{get_from_asciistring};
+use std::thread;
+
+use crate::esp;
+use crate::io;
+use crate::io::{Spectrum};
+use crate::jdx;
+use crate::jdx::{Encoding};
+use crate::out;
+use crate::out::{Format, Grid, Normalization};
 use crate::plt::{Chart, Spectra};
+use crate::prj;
+use crate::qcm;
+use crate::sim;
 use crate::sim::{Simulator};
+use crate::stk;
 use crate::ent::{Radical};
 use crate::ui::settings::{Settings};
+use crate::ui::simulation::{SimSettings};
+
+const MC_BATCH: usize = 16;  // Trials per MC step
+type Writer = dyn Fn(&Path) -> std::io::Result<()>;  // Saves to the chosen path
 
 pub struct Gui {
     // Main window
     pub builder: gtk::Builder,
     pub win: gtk::ApplicationWindow,
     pub drawing_area: gtk::DrawingArea,
-    pub open_sender: glib::Sender<String>,
+    pub open_sender: glib::Sender<Spectrum>,
     pub nucpar_sender: glib::Sender<(usize, usize, String, String, f64)>,
     pub radpar_sender: glib::Sender<(usize, String, String, f64)>,
     pub radgen_sender: glib::Sender<(usize, bool)>,  // Index + "insert to" or "remove from"
     pub sim: Simulator,
     pub chart: Chart,
+    pub project: Rc<RefCell<Option<PathBuf>>>,  // File of the current project, once saved or opened
 }
 
 impl Gui {
@@ -72,14 +88,15 @@ impl Gui {
 
         // let da = drawing_area.clone();  // Pass to the next function. TODO: remove
         // Opening a file...
+        let filename_lbl: gtk::Label =
+            builder.get_object("filename_lbl").expect("err building filename_lbl");
         let sim_clone = sim.clone();
-        open_receiver.attach(None, move |msg: String| {
-            let new_exp: &str = &msg[..];  // String to &str
-            let new_exp = get_from_asciistring(new_exp);  // Extract intensity vector
-            let mut exp_mut = sim_clone.exp.lock().unwrap();
-            *exp_mut = new_exp;  // Pass it to simulator
-
-            // TODO: Draw
+        let da = drawing_area.clone();
+        open_receiver.attach(None, move |new_exp: Spectrum| {
+            let source = new_exp.source.clone();
+            sim_clone.set_exp(new_exp);  // Pass it to simulator, with its field axis
+            show_grid(&filename_lbl, source.as_deref(), &sim_clone);
+            da.queue_draw();
 
             // Returning false here would close the receiver
             // and have senders fail
@@ -101,7 +118,27 @@ impl Gui {
             // Debugger
             // println!("Radical n.{}\n{}\n{}\n{}\n", new_par.0, new_par.1, new_par.2, new_par.3);
             let mut rads = sim_rads_clone.lock().unwrap();
-            rads[new_par.0] = rads[new_par.0].set_radpar(new_par.1, new_par.2, new_par.3);
+            let (rad_idx, field, sub_field, new_val) = new_par;
+            rads[rad_idx] = if field == "partner" {
+                // None, a free electron or a copy of radical k (sent as k+2)
+                let mut rad = rads[rad_idx].clone();
+                rad.partner = None;
+                let partner = match new_val as usize {
+                    0 => None,
+                    1 => Some(Radical::electron()),
+                    k => rads.get(k-2).cloned(),
+                };
+                match partner {
+                    Some(mut partner) => {
+                        partner.partner = None;
+                        let exch_j = rad.exch_j.val;
+                        Radical::biradical(rad, partner, exch_j)
+                    },
+                    None => rad,
+                }
+            } else {
+                rads[rad_idx].set_radpar(field, sub_field, new_val)
+            };
             glib::Continue(true)
         });
 
@@ -122,6 +159,7 @@ impl Gui {
             radgen_sender,
             sim,
             chart,
+            project: Rc::new(RefCell::new(None)),
         }  // return Gui
     }  // new(application)
 
@@ -129,14 +167,35 @@ impl Gui {
         let menu_bar = gio::Menu::new();
         let file_menu = gio::Menu::new();
         file_menu.append(Some("Open"), Some("app.open"));
+        file_menu.append(Some("Open Project"), Some("app.open_project"));
+        file_menu.append(Some("Save"), Some("app.save"));
+        file_menu.append(Some("Save As"), Some("app.save_as"));
+
+        let export_menu = gio::Menu::new();
+        export_menu.append(Some("Experimental (JCAMP-DX)"), Some("app.export_exp_jdx"));
+        export_menu.append(Some("Experimental (JCAMP-DX, AFFN)"), Some("app.export_exp_jdx_affn"));
+        export_menu.append(Some("Simulated (JCAMP-DX)"), Some("app.export_sim_jdx"));
+        export_menu.append(Some("Simulated (JCAMP-DX, AFFN)"), Some("app.export_sim_jdx_affn"));
+        export_menu.append(Some("Spectra (CSV, TSV, ASCII)"), Some("app.export_spectra"));
+        export_menu.append(Some("Stick Spectrum (ASCII)"), Some("app.export_sticks"));
+        export_menu.append(Some("EasySpin Script"), Some("app.export_easyspin"));
+        file_menu.append_submenu(Some("Export"), &export_menu);
+        file_menu.append(Some("Import EasySpin Script"), Some("app.import_easyspin"));
+        file_menu.append(Some("Import Hyperfine (ORCA, Gaussian)"), Some("app.import_hyperfine"));
         menu_bar.append_submenu(Some("File"), &file_menu);
 
+        let simulation_menu = gio::Menu::new();
+        simulation_menu.append(Some("Settings"), Some("app.simulation_settings"));
+        simulation_menu.append(Some("Exchange Rates"), Some("app.exchange_rates"));
+        simulation_menu.append(Some("Power Saturation Series"), Some("app.power_series"));
+        menu_bar.append_submenu(Some("Simulation"), &simulation_menu);
+
         menu_bar
     }  // build_system_menu
 
     pub fn open_action(&self) -> gio::SimpleAction {
         let window: &gtk::ApplicationWindow = &self.win;
-        let sender: glib::Sender<String> = self.open_sender.clone();
+        let sender: glib::Sender<Spectrum> = self.open_sender.clone();
 
         let open = gio::SimpleAction::new("open", None);
         open.connect_activate(clone!(@weak window => move |_, _| {
@@ -150,27 +209,427 @@ impl Gui {
                 ("Open", gtk::ResponseType::Ok),
                 ("Cancel", gtk::ResponseType::Cancel),
             ]);
+            let options = AsciiOptions::new();
+            file_chooser.set_extra_widget(&options.grid);
 
             let open_sender = sender.clone();
-            file_chooser.connect_response(move |file_chooser, response| {
+            file_chooser.connect_response(clone!(@weak window => move |file_chooser, response| {
                 if response == gtk::ResponseType::Ok {
                     let filename = file_chooser.get_filename().expect("Couldn't get filename");
-                    let file = File::open(&filename).expect("Couldn't open file");
-                    let mut reader = BufReader::new(file);
-                    let mut contents = String::new();
-                    let _ = reader.read_to_string(&mut contents);
 
-                    // Send contents to the main Context
-                    open_sender.send(contents).unwrap();
+                    // ASCII, Bruker BES3T, WinEPR or JCAMP-DX, by extension; send the spectrum to the main Context
+                    match io::open_with(&filename, &options.format()) {
+                        Ok(spectrum) => open_sender.send(spectrum).unwrap(),
+                        Err(error) => error_dialog(&window, &format!("Couldn't open {}\n{}", filename.display(), error)),
+                    }
                 }
                 file_chooser.close();
-            });
+            }));
 
             file_chooser.show_all();
         }));
         open
     }  // return open_action
 
+    // Actions writing the experimental or simulated spectrum as JCAMP-DX, compressed or plain
+    pub fn export_actions(&self) -> Vec<gio::SimpleAction> {
+        let mut actions = Vec::new();
+        for &(suffix, encoding) in [("", Encoding::DifDup), ("_affn", Encoding::Affn)].iter() {
+            let sim = self.sim.clone();
+            actions.push(self.save_action(&format!("export_exp_jdx{}", suffix), "experimental.jdx", move |path| {
+                std::fs::write(path, jdx::to_jcamp(&sim.exp.lock().unwrap(), "Experimental spectrum", encoding))
+            }));
+            let sim = self.sim.clone();
+            actions.push(self.save_action(&format!("export_sim_jdx{}", suffix), "simulation.jdx", move |path| {
+                std::fs::write(path, jdx::to_jcamp(&sim.teor_spectrum(), "Simulated spectrum", encoding))
+            }));
+        }
+        actions.push(self.export_spectra_action());
+
+        // Field and intensity of every line of the current radicals
+        let sim = self.sim.clone();
+        actions.push(self.save_action("export_sticks", "sticks.txt", move |path| {
+            let rads = sim.rads.lock().unwrap().clone();
+            std::fs::write(path, stk::to_ascii(&sim.stick_spectrum(rads)))
+        }));
+
+        let sim = self.sim.clone();
+        actions.push(self.save_action("export_easyspin", "model.m", move |path| {
+            let rads = sim.rads.lock().unwrap().clone();
+            std::fs::write(path, esp::to_easyspin(&sim, &rads))
+        }));
+        actions.push(self.import_easyspin_action());
+        actions.push(self.import_hyperfine_action());
+        actions
+    }
+
+    // New radical from the couplings and g of an ORCA or Gaussian output; equivalent nuclei within the tolerance
+    pub fn import_hyperfine_action(&self) -> gio::SimpleAction {
+        let window: &gtk::ApplicationWindow = &self.win;
+        let sim = self.sim.clone();
+
+        let action = gio::SimpleAction::new("import_hyperfine", None);
+        action.connect_activate(clone!(@weak window => move |_, _| {
+            let file_chooser = gtk::FileChooserDialog::new(
+                Some("Import Hyperfine Couplings"),
+                Some(&window),
+                gtk::FileChooserAction::Open,
+            );
+            file_chooser.add_buttons(&[
+                ("Open", gtk::ResponseType::Ok),
+                ("Cancel", gtk::ResponseType::Cancel),
+            ]);
+
+            // Tolerance (G) for equivalent nuclei
+            let tol_spin = gtk::SpinButton::with_range(0.0, 10.0, 0.05);
+            tol_spin.set_digits(2);
+            tol_spin.set_value(0.1);
+            let options = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+            options.pack_start(&gtk::Label::new(Some("Equivalence tolerance (G)")), false, false, 0);
+            options.pack_start(&tol_spin, false, false, 0);
+            options.show_all();
+            file_chooser.set_extra_widget(&options);
+
+            let sim = sim.clone();
+            file_chooser.connect_response(clone!(@weak window => move |file_chooser, response| {
+                if response == gtk::ResponseType::Ok {
+                    let filename = file_chooser.get_filename().expect("Couldn't get filename");
+                    let freq = *sim.freq.lock().unwrap();
+                    let predicted = std::fs::read_to_string(&filename).map_err(io::ImportError::from)
+                        .and_then(|content| qcm::read(&content));
+                    match predicted {
+                        Ok(prediction) => {
+                            // Couplings and equivalent groups, for a look before adding the radical
+                            let tol = tol_spin.get_value();
+                            let dialog = gtk::Dialog::with_buttons(
+                                Some("Import Hyperfine Couplings"),
+                                Some(&window),
+                                gtk::DialogFlags::MODAL,
+                                &[("Add Radical", gtk::ResponseType::Ok), ("Cancel", gtk::ResponseType::Cancel)],
+                            );
+                            let report = gtk::Label::new(Some(&prediction.report(tol)));
+                            report.set_selectable(true);
+                            dialog.get_content_area().pack_start(&report, true, true, 6);
+                            dialog.show_all();
+                            if dialog.run() == gtk::ResponseType::Ok {
+                                sim.rads.lock().unwrap().push(prediction.radical(freq, tol));
+                                *sim.sigma.lock().unwrap() = sim::UNSCORED;
+                            }
+                            dialog.close();
+                        },
+                        Err(error) => error_dialog(&window, &format!("Couldn't import {}\n{}", filename.display(), error)),
+                    }
+                }
+                file_chooser.close();
+            }));
+
+            file_chooser.show_all();
+        }));
+        action
+    }  // return import_hyperfine_action
+
+    // Radicals and settings from an EasySpin script, replacing the current ones
+    pub fn import_easyspin_action(&self) -> gio::SimpleAction {
+        let window: &gtk::ApplicationWindow = &self.win;
+        let sim = self.sim.clone();
+
+        let action = gio::SimpleAction::new("import_easyspin", None);
+        action.connect_activate(clone!(@weak window => move |_, _| {
+            let file_chooser = gtk::FileChooserDialog::new(
+                Some("Import EasySpin Script"),
+                Some(&window),
+                gtk::FileChooserAction::Open,
+            );
+            file_chooser.add_buttons(&[
+                ("Open", gtk::ResponseType::Ok),
+                ("Cancel", gtk::ResponseType::Cancel),
+            ]);
+
+            let sim = sim.clone();
+            file_chooser.connect_response(clone!(@weak window => move |file_chooser, response| {
+                if response == gtk::ResponseType::Ok {
+                    let filename = file_chooser.get_filename().expect("Couldn't get filename");
+                    let freq = *sim.freq.lock().unwrap();
+                    let imported = std::fs::read_to_string(&filename).map_err(|error| error.to_string())
+                        .and_then(|script| esp::from_easyspin(&script, freq).map_err(|error| error.to_string()));
+                    match imported {
+                        Ok((rads, exp)) => {
+                            exp.apply(&sim);
+                            *sim.rads.lock().unwrap() = rads;
+                            *sim.sigma.lock().unwrap() = sim::UNSCORED;
+                        },
+                        Err(error) => error_dialog(&window, &format!("Couldn't import {}\n{}", filename.display(), error)),
+                    }
+                }
+                file_chooser.close();
+            }));
+
+            file_chooser.show_all();
+        }));
+        action
+    }  // return import_easyspin_action
+
+    // Field, exp, simulated, residual and radicals as columns; format, grid and normalization next to the file name
+    pub fn export_spectra_action(&self) -> gio::SimpleAction {
+        let window: &gtk::ApplicationWindow = &self.win;
+        let sim = self.sim.clone();
+
+        let action = gio::SimpleAction::new("export_spectra", None);
+        action.connect_activate(clone!(@weak window => move |_, _| {
+            let file_chooser = gtk::FileChooserDialog::new(
+                Some("Export Spectra"),
+                Some(&window),
+                gtk::FileChooserAction::Save,
+            );
+            file_chooser.add_buttons(&[
+                ("Save", gtk::ResponseType::Ok),
+                ("Cancel", gtk::ResponseType::Cancel),
+            ]);
+            file_chooser.set_do_overwrite_confirmation(true);
+            file_chooser.set_current_name("spectra.csv");
+
+            // Options
+            let combo = |items: &[&str]| {
+                let combo = gtk::ComboBoxText::new();
+                for item in items { combo.append_text(item); }
+                combo.set_active(Some(0));
+                combo
+            };
+            let format_combo = combo(&["CSV", "TSV", "ASCII (index, field, simulated)"]);
+            let grid_combo = combo(&["Simulation grid", "Experimental points"]);
+            let norm_combo = combo(&["Not normalized", "Simulated scaled to experimental", "Maximum 1"]);
+            let options = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+            options.pack_start(&format_combo, false, false, 0);
+            options.pack_start(&grid_combo, false, false, 0);
+            options.pack_start(&norm_combo, false, false, 0);
+            options.show_all();
+            file_chooser.set_extra_widget(&options);
+
+            let format = |combo: &gtk::ComboBoxText| match combo.get_active() {
+                Some(1) => Format::Tsv,
+                Some(2) => Format::Ascii,
+                _ => Format::Csv,
+            };
+
+            // The extension follows the format
+            format_combo.connect_changed(clone!(@weak file_chooser => move |combo| {
+                let name = file_chooser.get_current_name().map_or(String::new(), |name| name.to_string());
+                let stem = Path::new(&name).file_stem().map_or("spectra".to_string(), |stem| stem.to_string_lossy().to_string());
+                file_chooser.set_current_name(format!("{}.{}", stem, format(combo).extension()));
+            }));
+
+            let sim = sim.clone();
+            file_chooser.connect_response(clone!(@weak window => move |file_chooser, response| {
+                if response == gtk::ResponseType::Ok {
+                    let filename = file_chooser.get_filename().expect("Couldn't get filename");
+                    let grid = if grid_combo.get_active() == Some(1) { Grid::Experimental } else { Grid::Simulation };
+                    let norm = match norm_combo.get_active() {
+                        Some(1) => Normalization::Fit,
+                        Some(2) => Normalization::Unit,
+                        _ => Normalization::None,
+                    };
+                    if let Err(error) = std::fs::write(&filename, out::export(&sim, format(&format_combo), grid, norm)) {
+                        error_dialog(&window, &format!("Couldn't save {}\n{}", filename.display(), error));
+                    }
+                }
+                file_chooser.close();
+            }));
+
+            file_chooser.show_all();
+        }));
+        action
+    }  // return export_spectra_action
+
+    // Simulation settings and reports on the current radicals
+    pub fn simulation_actions(&self) -> Vec<gio::SimpleAction> {
+        let window: &gtk::ApplicationWindow = &self.win;
+        let sim = self.sim.clone();
+
+        // Instrument settings that no radical owns
+        let settings = gio::SimpleAction::new("simulation_settings", None);
+        let da = self.drawing_area.clone();
+        settings.connect_activate(clone!(@weak window => move |_, _| {
+            let settings = SimSettings::new(&sim, &da);
+            settings.window.set_transient_for(Some(&window));
+            settings.window.show_all();
+        }));
+
+        // Exchange frequencies as rates, concentrations and O2 partial pressures
+        let sim = self.sim.clone();
+        let exchange = gio::SimpleAction::new("exchange_rates", None);
+        exchange.connect_activate(clone!(@weak window => move |_, _| {
+            let dialog = gtk::Dialog::with_buttons(
+                Some("Exchange Rates"),
+                Some(&window),
+                gtk::DialogFlags::MODAL,
+                &[("Close", gtk::ResponseType::Close)],
+            );
+
+            let k_ex = gtk::Entry::new();
+            k_ex.set_text("3.5e9");
+            let solubility = gtk::Entry::new();
+            solubility.set_text("1.7e-6");
+            let report = gtk::Label::new(None);
+            report.set_selectable(true);
+
+            let grid = gtk::Grid::new();
+            grid.set_row_spacing(6);
+            grid.set_column_spacing(6);
+            grid.attach(&gtk::Label::new(Some("Rate constant (M^-1 s^-1)")), 0, 0, 1, 1);
+            grid.attach(&k_ex, 1, 0, 1, 1);
+            grid.attach(&gtk::Label::new(Some("O2 solubility (M/mmHg)")), 0, 1, 1, 1);
+            grid.attach(&solubility, 1, 1, 1, 1);
+            grid.attach(&report, 0, 2, 2, 1);
+            dialog.get_content_area().pack_start(&grid, true, true, 6);
+
+            let sim = sim.clone();
+            let update = Rc::new(clone!(@weak k_ex, @weak solubility, @weak report => move || {
+                let k = k_ex.get_text().as_str().parse::<f64>();
+                let sol = solubility.get_text().as_str().parse::<f64>();
+                match (k, sol) {
+                    (Ok(k), Ok(sol)) => report.set_text(&sim.exchange_report(k, sol)),
+                    _ => report.set_text("Not a number"),
+                }
+            }));
+            update();
+            let changed = Rc::clone(&update);
+            k_ex.connect_changed(move |_| changed());
+            solubility.connect_changed(move |_| update());
+
+            dialog.show_all();
+            dialog.run();
+            dialog.close();
+        }));
+
+        // Power saturation: spectra recorded at different powers, from several files or a BES3T power sweep
+        let sim = self.sim.clone();
+        let series = gio::SimpleAction::new("power_series", None);
+        series.connect_activate(clone!(@weak window => move |_, _| {
+            let file_chooser = gtk::FileChooserDialog::new(
+                Some("Open Power Series"),
+                Some(&window),
+                gtk::FileChooserAction::Open,
+            );
+            file_chooser.add_buttons(&[
+                ("Open", gtk::ResponseType::Ok),
+                ("Cancel", gtk::ResponseType::Cancel),
+            ]);
+            file_chooser.set_select_multiple(true);
+
+            let sim = sim.clone();
+            file_chooser.connect_response(clone!(@weak window => move |file_chooser, response| {
+                if response == gtk::ResponseType::Ok {
+                    let opened: Result<Vec<Vec<Spectrum>>, String> = file_chooser.get_filenames().iter()
+                        .map(|filename| io::open_series(filename).map_err(|error| format!("Couldn't open {}\n{}", filename.display(), error)))
+                        .collect();
+                    let spectra: Result<Vec<Spectrum>, String> = opened.map(|series| series.into_iter().flatten().collect());
+                    match spectra {
+                        Ok(spectra) => match spectra.iter().map(|spec| spec.acq.power).collect::<Option<Vec<f64>>>() {
+                            Some(powers) if powers.len() >= 3 => {
+                                let (fit, ints) = sim.power_series(&powers, &spectra);
+                                info_dialog(&window, &fit.report(&powers, &ints));
+                            },
+                            Some(_) => error_dialog(&window, "A power series needs at least three spectra"),
+                            None => error_dialog(&window, "Some spectra don't give the microwave power"),
+                        },
+                        Err(error) => error_dialog(&window, &error),
+                    }
+                }
+                file_chooser.close();
+            }));
+
+            file_chooser.show_all();
+        }));
+
+        vec![settings, exchange, series]
+    }  // return simulation_actions
+
+    // Action asking for a file name and writing there
+    pub fn save_action<F: Fn(&Path) -> std::io::Result<()> + 'static>(&self, name: &str, suggested: &str, write: F) -> gio::SimpleAction {
+        let window: &gtk::ApplicationWindow = &self.win;
+        let write: Rc<Writer> = Rc::new(write);
+        let suggested = suggested.to_string();
+
+        let action = gio::SimpleAction::new(name, None);
+        action.connect_activate(clone!(@weak window => move |_, _| {
+            save_dialog(&window, &suggested, Rc::clone(&write));
+        }));
+        action
+    }  // return save_action
+
+    // Save, Save As and Open Project
+    pub fn project_actions(&self) -> Vec<gio::SimpleAction> {
+        let window: &gtk::ApplicationWindow = &self.win;
+
+        // Save As remembers the file for the next Save
+        let sim = self.sim.clone();
+        let project = Rc::clone(&self.project);
+        let write: Rc<Writer> = Rc::new(clone!(@weak window => @default-return Ok(()), move |path: &Path| {
+            prj::save(&sim, path)?;
+            *project.borrow_mut() = Some(path.to_path_buf());
+            set_title(&window, path);
+            Ok(())
+        }));
+
+        let save_as = gio::SimpleAction::new("save_as", None);
+        save_as.connect_activate(clone!(@weak window, @strong write => move |_, _| {
+            save_dialog(&window, "project.gfp", Rc::clone(&write));
+        }));
+
+        let save = gio::SimpleAction::new("save", None);
+        let project = Rc::clone(&self.project);
+        save.connect_activate(clone!(@weak window, @strong write => move |_, _| {
+            let path = project.borrow().clone();
+            match path {
+                Some(path) => if let Err(error) = write(&path) {
+                    error_dialog(&window, &format!("Couldn't save {}\n{}", path.display(), error));
+                },
+                None => save_dialog(&window, "project.gfp", Rc::clone(&write)),
+            }
+        }));
+
+        // The grid report goes under the file name, as for an opened spectrum
+        let open_project = gio::SimpleAction::new("open_project", None);
+        let filename_lbl: gtk::Label = self.builder.get_object("filename_lbl").expect("err building filename_lbl");
+        let da = self.drawing_area.clone();
+        let sim = self.sim.clone();
+        let project = Rc::clone(&self.project);
+        open_project.connect_activate(clone!(@weak window, @weak filename_lbl, @weak da => move |_, _| {
+            let file_chooser = gtk::FileChooserDialog::new(
+                Some("Open Project"),
+                Some(&window),
+                gtk::FileChooserAction::Open,
+            );
+            file_chooser.add_buttons(&[
+                ("Open", gtk::ResponseType::Ok),
+                ("Cancel", gtk::ResponseType::Cancel),
+            ]);
+
+            let sim = sim.clone();
+            let project = Rc::clone(&project);
+            file_chooser.connect_response(clone!(@weak window, @weak filename_lbl, @weak da => move |file_chooser, response| {
+                if response == gtk::ResponseType::Ok {
+                    let filename = file_chooser.get_filename().expect("Couldn't get filename");
+                    match prj::load(&filename).and_then(|loaded| loaded.apply(&sim)) {
+                        Ok(()) => {
+                            *project.borrow_mut() = Some(filename.clone());
+                            set_title(&window, &filename);
+                            let source = sim.exp.lock().unwrap().source.clone();
+                            show_grid(&filename_lbl, source.as_deref(), &sim);
+                            da.queue_draw();
+                        },
+                        Err(error) => error_dialog(&window, &format!("Couldn't open {}\n{}", filename.display(), error)),
+                    }
+                }
+                file_chooser.close();
+            }));
+
+            file_chooser.show_all();
+        }));
+
+        vec![save, save_as, open_project]
+    }  // return project_actions
+
     pub fn connect_buttons(&self) {
         // SETTINGS BUTTON
         let settings_btn: gtk::Button =
@@ -240,6 +699,14 @@ impl Gui {
 
         });
 
+        // Plot of the experiment on the simulation grid and of the last simulation
+        let sim_clone = self.sim.clone();
+        let chart = self.chart;
+        self.drawing_area.connect_draw(move |_da: &gtk::DrawingArea, cr: &cairo::Context| {
+            let teor = sim_clone.teor.lock().unwrap().clone();
+            chart.draw_spectra(cr, Spectra { exp: sim_clone.exp_on_grid(), teor })
+        });
+
         // EXPERIMENTAL BUTTON
         let exp_btn: gtk::Button =
             self.builder.get_object("experimental_btn").expect("err building exp_btn");
@@ -247,44 +714,202 @@ impl Gui {
         // Clone vars to move them in the next closure
         let sim_clone = self.sim.clone();
         let da = self.drawing_area.clone();
-        let chart = self.chart.clone();
         let sim_rads_clone = self.sim.rads.clone();
+        let window = self.win.clone();
 
         exp_btn.connect_clicked(move |_| {
-            // Move sim mutex in here
-            let mut sim_teor_guard = sim_clone.teor.lock().unwrap();
-            let sim_exp_guard = sim_clone.exp.lock().unwrap();
-            let sim_rads_guard = sim_rads_clone.lock().unwrap();
             // Update teor with internal rads
-            let sim_rads_deref = &*sim_rads_guard;
-            *sim_teor_guard = sim_clone.calcola(sim_rads_deref.clone());
-            // Move sim vectors in the next closure
-            let sim_teor_clone = sim_teor_guard.clone();
-            let sim_exp_clone = sim_exp_guard.clone();
-            // Draw spectra
-            da.connect_draw(move |_da: &gtk::DrawingArea, cr: &cairo::Context| {
-                chart.draw_spectra(cr, Spectra { exp: sim_exp_clone.clone(), teor: sim_teor_clone.clone() })
-            });
+            let rads = sim_rads_clone.lock().unwrap().clone();
+            *sim_clone.teor.lock().unwrap() = sim_clone.calcola(rads.clone());
+            da.queue_draw();
+
+            let fallbacks = Simulator::fallbacks(&rads);
+            if !fallbacks.is_empty() {
+                let lines: Vec<String> = fallbacks.iter()
+                    .map(|(k, error)| format!("Radical {}: {}", k, error)).collect();
+                error_dialog(&window, &format!("{}\nSimulated to first order.", lines.join("\n")));
+            }
         });
 
         // MONTECARLO BUTTON
         let mc_go_btn: gtk::Button =
             self.builder.get_object("mc_go_btn").expect("err building mc_go_button");
 
-        let sim = self.sim.clone();
-
-        // On clicked button
-        mc_go_btn.connect_clicked(move |_| {
-            println!("Result pre-scope: {}", *sim.mc_go.lock().unwrap());
+        // Batches run on their own thread while mc_go is set: true after an improvement, false once stopped
+        let (mc_sender, mc_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
 
-            {
-                let mut sim_mcgo_guard = sim.mc_go.lock().unwrap();
-                *sim_mcgo_guard = !*sim_mcgo_guard;
-                println!("Result in-scope: {}", sim_mcgo_guard);
+        let sim = self.sim.clone();
+        let da = self.drawing_area.clone();
+        let mc_btn = mc_go_btn.clone();
+        mc_receiver.attach(None, move |improved: bool| {
+            if improved {
+                let rads = sim.rads.lock().unwrap().clone();
+                *sim.teor.lock().unwrap() = sim.calcola(rads);
+                da.queue_draw();
+            } else {
+                mc_btn.set_label("Start MC");
+                mc_btn.set_sensitive(true);
             }
+            glib::Continue(true)
+        });
 
-            println!("Result post-scope: {}", *sim.mc_go.lock().unwrap());
+        let sim = self.sim.clone();
+        mc_go_btn.connect_clicked(move |btn| {
+            let go = {
+                let mut go = sim.mc_go.lock().unwrap();
+                *go = !*go;
+                *go
+            };
+
+            if go {
+                btn.set_label("Stop MC");
+                let sim = sim.clone();
+                let mc_sender = mc_sender.clone();
+                thread::spawn(move || {
+                    // The radicals may have been edited since the last batch
+                    sim.score();
+                    while *sim.mc_go.lock().unwrap() {
+                        if sim.mc_batch(MC_BATCH) { mc_sender.send(true).unwrap(); }
+                    }
+                    mc_sender.send(false).unwrap();
+                });
+            } else {
+                // Until the running batch is over
+                btn.set_label("Stopping MC");
+                btn.set_sensitive(false);
+            }
         });
     }
 
 }  // impl GuiData
+
+// Options of text files in the open dialog; Auto and 0 leave them to detection
+struct AsciiOptions {
+    grid: gtk::Grid,
+    delimiter: gtk::ComboBoxText,
+    decimal: gtk::ComboBoxText,
+    fld_col: gtk::SpinButton,
+    int_col: gtk::SpinButton,
+    skip: gtk::SpinButton,
+}
+
+impl AsciiOptions {
+    fn new() -> Self {
+        let delimiter = gtk::ComboBoxText::new();
+        for (id, text) in [("auto", "Auto"), ("tab", "Tab"), ("comma", "Comma"), ("semicolon", "Semicolon"), ("space", "Space")].iter() {
+            delimiter.append(Some(id), text);
+        }
+        delimiter.set_active_id(Some("auto"));
+        let decimal = gtk::ComboBoxText::new();
+        for (id, text) in [("auto", "Auto"), ("point", "Point"), ("comma", "Comma")].iter() {
+            decimal.append(Some(id), text);
+        }
+        decimal.set_active_id(Some("auto"));
+        let fld_col = gtk::SpinButton::with_range(0.0, 99.0, 1.0);
+        let int_col = gtk::SpinButton::with_range(0.0, 99.0, 1.0);
+        let skip = gtk::SpinButton::with_range(0.0, 9999.0, 1.0);
+
+        let grid = gtk::Grid::new();
+        grid.set_column_spacing(6);
+        let widgets: [(&str, &gtk::Widget); 5] = [
+            ("Separator", delimiter.upcast_ref()),
+            ("Decimal", decimal.upcast_ref()),
+            ("Field Column", fld_col.upcast_ref()),
+            ("Intensity Column", int_col.upcast_ref()),
+            ("Skip Lines", skip.upcast_ref()),
+        ];
+        for (col, (label, widget)) in widgets.iter().enumerate() {
+            grid.attach(&gtk::Label::new(Some(label)), col as i32, 0, 1, 1);
+            grid.attach(*widget, col as i32, 1, 1, 1);
+        }
+        grid.show_all();
+
+        AsciiOptions { grid, delimiter, decimal, fld_col, int_col, skip }
+    }
+
+    fn format(&self) -> io::AsciiFormat {
+        let active = |combo: &gtk::ComboBoxText| combo.get_active_id().map(|id| id.as_str().to_string());
+        let column = |spin: &gtk::SpinButton| match spin.get_value_as_int() { 0 => None, col => Some(col as usize-1) };
+        io::AsciiFormat {
+            delimiter: match active(&self.delimiter).as_deref() {
+                Some("tab") => Some('\t'),
+                Some("comma") => Some(','),
+                Some("semicolon") => Some(';'),
+                Some("space") => Some(' '),
+                _ => None,
+            },
+            decimal_comma: match active(&self.decimal).as_deref() {
+                Some("point") => Some(false),
+                Some("comma") => Some(true),
+                _ => None,
+            },
+            fld_col: column(&self.fld_col),
+            int_col: column(&self.int_col),
+            skip: self.skip.get_value_as_int() as usize,
+            ..io::AsciiFormat::default()
+        }
+    }
+}
+
+// File name and grid report of the spectrum just loaded
+fn show_grid(label: &gtk::Label, source: Option<&Path>, sim: &Simulator) {
+    let name = source.and_then(|path| path.file_name()).map_or(String::new(), |name| format!("{}\n", name.to_string_lossy()));
+    label.set_text(&format!("{}{}", name, sim.grid_report().trim_end()));
+}
+
+// Ask for a file name, then write; errors go to a dialog
+fn save_dialog(window: &gtk::ApplicationWindow, suggested: &str, write: Rc<Writer>) {
+    let file_chooser = gtk::FileChooserDialog::new(
+        Some("Save File"),
+        Some(window),
+        gtk::FileChooserAction::Save,
+    );
+    file_chooser.add_buttons(&[
+        ("Save", gtk::ResponseType::Ok),
+        ("Cancel", gtk::ResponseType::Cancel),
+    ]);
+    file_chooser.set_do_overwrite_confirmation(true);
+    file_chooser.set_current_name(suggested);
+
+    file_chooser.connect_response(clone!(@weak window => move |file_chooser, response| {
+        if response == gtk::ResponseType::Ok {
+            let filename = file_chooser.get_filename().expect("Couldn't get filename");
+            if let Err(error) = write(&filename) {
+                error_dialog(&window, &format!("Couldn't save {}\n{}", filename.display(), error));
+            }
+        }
+        file_chooser.close();
+    }));
+
+    file_chooser.show_all();
+}
+
+fn error_dialog(window: &gtk::ApplicationWindow, text: &str) {
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Error,
+        gtk::ButtonsType::Close,
+        text,
+    );
+    dialog.run();
+    dialog.close();
+}
+
+fn info_dialog(window: &gtk::ApplicationWindow, text: &str) {
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Info,
+        gtk::ButtonsType::Close,
+        text,
+    );
+    dialog.run();
+    dialog.close();
+}
+
+// Window title with the project name
+fn set_title(window: &gtk::ApplicationWindow, path: &Path) {
+    let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+    window.set_title(&format!("g Factor - {}", name));
+}